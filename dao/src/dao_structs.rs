@@ -1,12 +1,21 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io;
 
+use crate::MembershipType;
+use indexmap::{IndexMap, IndexSet};
 use near_sdk::{
-    env,
+    borsh::{self, BorshDeserialize, BorshSerialize},
     json_types::{U128, U64},
     serde::{Deserialize, Serialize},
+    serde_json::{self, Map, Value},
     AccountId,
 };
 
+// These structs mirror the policy of Sputnik/Astro DAO contract. They are deserialized from
+// `get_policy` and sent back in `ChangePolicy` proposals, so anything this contract doesn't know
+// about must be kept as is: unknown fields go to `extra`, unknown enum variants to `Other`,
+// and sets and maps keep the order they came in.
+
 #[derive(Deserialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct Policy {
@@ -22,24 +31,148 @@ pub struct Policy {
     pub bounty_bond: U128,
     /// Period in which giving up on bounty is not punished.
     pub bounty_forgiveness_period: U64,
+    /// Fields that are not known to this contract.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 impl Policy {
-    pub fn update_dao_members(&mut self, new_members: HashSet<AccountId>, members_role: String) {
-        let index = &self
+    /// Add an empty `Group` role named `members_role` built from `template` unless the policy
+    /// already has a role with this name. Returns true if the role was added.
+    pub fn add_missing_role(&mut self, members_role: &str, template: &RoleTemplate) -> bool {
+        if self.roles.iter().any(|role| role.name == members_role) {
+            return false;
+        }
+
+        self.roles.push(RolePermission {
+            name: members_role.to_string(),
+            kind: RoleKind::Group(IndexSet::new()),
+            permissions: template.permissions.iter().cloned().collect(),
+            vote_policy: template
+                .vote_policy
+                .iter()
+                .map(|(kind, vote_policy)| (kind.clone(), vote_policy.clone()))
+                .collect(),
+            extra: Map::new(),
+        });
+        true
+    }
+
+    /// Replace members of the `Group` role named `members_role` with `new_members`.
+    /// The role keeps its position in `roles`.
+    pub fn update_dao_members(
+        &mut self,
+        new_members: HashSet<AccountId>,
+        members_role: &str,
+    ) -> Result<MembershipSummary, String> {
+        let role_permission = self
             .roles
+            .iter_mut()
+            .find(|role| role.name == members_role)
+            .ok_or_else(|| role_not_found(members_role))?;
+
+        let current_members = match &mut role_permission.kind {
+            RoleKind::Group(members) => members,
+            _ => return Err(wrong_role_kind(members_role)),
+        };
+
+        let unchanged = current_members
             .iter()
-            .position(|role| role.name == members_role)
-            .expect("Role not found");
+            .filter(|account_id| new_members.contains(*account_id))
+            .count() as u64;
+        let summary = MembershipSummary {
+            added: new_members.len() as u64 - unchanged,
+            removed: current_members.len() as u64 - unchanged,
+            unchanged,
+        };
 
-        let mut role_permission = self.roles.remove(*index);
+        let new_members: BTreeSet<AccountId> = new_members.into_iter().collect();
+        *current_members = new_members.into_iter().collect();
+        Ok(summary)
+    }
 
-        match role_permission.kind {
-            RoleKind::Group(_) => role_permission.kind = RoleKind::Group(new_members),
-            _ => env::panic_str("Wrong role kind"),
+    /// Current members of the `Group` role named `members_role`.
+    pub fn dao_members(&self, members_role: &str) -> Result<&IndexSet<AccountId>, String> {
+        let role_permission = self
+            .roles
+            .iter()
+            .find(|role| role.name == members_role)
+            .ok_or_else(|| role_not_found(members_role))?;
+
+        match &role_permission.kind {
+            RoleKind::Group(members) => Ok(members),
+            _ => Err(wrong_role_kind(members_role)),
         }
+    }
+
+    /// Compare members of `members_role` with `new_members`.
+    /// Returns accounts that have to be added to or removed from the role, sorted by account id.
+    pub fn membership_changes(
+        &self,
+        new_members: &HashSet<AccountId>,
+        members_role: &str,
+    ) -> Result<Vec<(AccountId, MembershipType)>, String> {
+        let current_members = self.dao_members(members_role)?;
+
+        let mut changes: Vec<(AccountId, MembershipType)> = new_members
+            .iter()
+            .filter(|account_id| !current_members.contains(*account_id))
+            .map(|account_id| (account_id.clone(), MembershipType::Add))
+            .chain(
+                current_members
+                    .iter()
+                    .filter(|account_id| !new_members.contains(*account_id))
+                    .map(|account_id| (account_id.clone(), MembershipType::Remove)),
+            )
+            .collect();
+        changes.sort_by(|a, b| a.0.cmp(&b.0));
+        Ok(changes)
+    }
+}
+
+fn role_not_found(members_role: &str) -> String {
+    format!("Role {} not found in DAO policy", members_role)
+}
 
-        self.roles.push(role_permission);
+fn wrong_role_kind(members_role: &str) -> String {
+    format!("Role {} of DAO policy is not a group", members_role)
+}
+
+/// Number of accounts added to, removed from and kept in a role by a membership update.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MembershipSummary {
+    pub added: u64,
+    pub removed: u64,
+    pub unchanged: u64,
+}
+
+/// Permissions and vote policy of the role that is added to the DAO policy
+/// when the holders role doesn't exist yet.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct RoleTemplate {
+    /// Set of actions on which proposals that this role is allowed to execute.
+    /// <proposal_kind>:<action>
+    pub permissions: BTreeSet<String>,
+    /// For each proposal kind, defines voting policy.
+    pub vote_policy: BTreeMap<String, VotePolicy>,
+}
+
+impl Default for RoleTemplate {
+    fn default() -> Self {
+        Self {
+            permissions: [
+                "*:AddProposal",
+                "*:VoteApprove",
+                "*:VoteReject",
+                "*:VoteRemove",
+            ]
+            .iter()
+            .map(|permission| permission.to_string())
+            .collect(),
+            vote_policy: BTreeMap::new(),
+        }
     }
 }
 
@@ -52,13 +185,16 @@ pub struct RolePermission {
     pub kind: RoleKind,
     /// Set of actions on which proposals that this role is allowed to execute.
     /// <proposal_kind>:<action>
-    pub permissions: HashSet<String>,
+    pub permissions: IndexSet<String>,
     /// For each proposal kind, defines voting policy.
-    pub vote_policy: HashMap<String, VotePolicy>,
+    pub vote_policy: IndexMap<String, VotePolicy>,
+    /// Fields that are not known to this contract.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
 }
 
 /// Defines configuration of the vote.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
 pub struct VotePolicy {
     /// Kind of weight to use for votes.
@@ -71,6 +207,25 @@ pub struct VotePolicy {
     pub quorum: U128,
     /// How many votes to pass this vote.
     pub threshold: WeightOrRatio,
+    /// Fields that are not known to this contract.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+// Vote policy is kept in contract state as a part of `RoleTemplate`. Fields that are not known
+// to this contract can't be stored with Borsh, so the whole policy is stored as JSON.
+impl BorshSerialize for VotePolicy {
+    fn serialize<W: io::Write>(&self, writer: &mut W) -> io::Result<()> {
+        let json = serde_json::to_string(self)?;
+        BorshSerialize::serialize(&json, writer)
+    }
+}
+
+impl BorshDeserialize for VotePolicy {
+    fn deserialize(buf: &mut &[u8]) -> io::Result<Self> {
+        let json: String = BorshDeserialize::deserialize(buf)?;
+        Ok(serde_json::from_str(&json)?)
+    }
 }
 
 #[derive(Deserialize, Serialize)]
@@ -81,24 +236,226 @@ pub enum RoleKind {
     /// Member greater or equal than given balance. Can use `1` as non-zero balance.
     Member(U128),
     /// Set of accounts.
-    Group(HashSet<AccountId>),
+    Group(IndexSet<AccountId>),
+    /// Kind that is not known to this contract.
+    #[serde(untagged)]
+    Other(Value),
 }
 
 /// Direct weight or ratio to total weight, used for the voting policy.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
 #[serde(untagged)]
 pub enum WeightOrRatio {
     Weight(U128),
     Ratio(u64, u64),
+    /// Threshold that is not known to this contract.
+    Other(Value),
 }
 
 /// How the voting policy votes get weigthed.
-#[derive(Deserialize, Serialize)]
+#[derive(Clone, Deserialize, Serialize)]
 #[serde(crate = "near_sdk::serde")]
 pub enum WeightKind {
     /// Using token amounts and total delegated at the moment.
     TokenWeight,
     /// Weight of the group role. Roles that don't have scoped group are not supported.
     RoleWeight,
+    /// Weight kind that is not known to this contract.
+    #[serde(untagged)]
+    Other(Value),
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::serde_json::{self, json};
+
+    use super::*;
+
+    const ASTRO_POLICY: &str = include_str!("../tests/fixtures/astro_policy.json");
+
+    fn accounts(ids: &[&str]) -> HashSet<AccountId> {
+        ids.iter().map(|id| id.parse().unwrap()).collect()
+    }
+
+    fn sample_policy(members: &[&str]) -> Policy {
+        serde_json::from_value(json!({
+            "roles": [
+                {
+                    "name": "council",
+                    "kind": { "Group": ["alice.near"] },
+                    "permissions": ["*:*"],
+                    "vote_policy": {}
+                },
+                {
+                    "name": "holders",
+                    "kind": { "Group": members },
+                    "permissions": ["*:AddProposal"],
+                    "vote_policy": {}
+                }
+            ],
+            "default_vote_policy": {
+                "weight_kind": "RoleWeight",
+                "quorum": "0",
+                "threshold": [1, 2]
+            },
+            "proposal_bond": "1000000000000000000000000",
+            "proposal_period": "604800000000000",
+            "bounty_bond": "1000000000000000000000000",
+            "bounty_forgiveness_period": "86400000000000"
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn test_membership_changes() {
+        let policy = sample_policy(&["bob.near", "carol.near"]);
+        let changes = policy
+            .membership_changes(&accounts(&["carol.near", "dave.near"]), "holders")
+            .unwrap();
+
+        assert_eq!(
+            changes,
+            vec![
+                ("bob.near".parse().unwrap(), MembershipType::Remove),
+                ("dave.near".parse().unwrap(), MembershipType::Add),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_membership_changes_in_sync() {
+        let policy = sample_policy(&["bob.near"]);
+        assert!(policy
+            .membership_changes(&accounts(&["bob.near"]), "holders")
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_membership_changes_unknown_role() {
+        let policy: Policy = serde_json::from_str(ASTRO_POLICY).unwrap();
+        assert_eq!(
+            policy.membership_changes(&accounts(&["bob.near"]), "whales"),
+            Err("Role whales not found in DAO policy".to_string())
+        );
+        assert_eq!(
+            policy.membership_changes(&accounts(&["bob.near"]), "delegates"),
+            Err("Role delegates of DAO policy is not a group".to_string())
+        );
+    }
+
+    #[test]
+    fn test_policy_round_trip() {
+        let policy: Policy = serde_json::from_str(ASTRO_POLICY).unwrap();
+        assert_eq!(
+            serde_json::to_string(&policy).unwrap(),
+            ASTRO_POLICY.trim_end()
+        );
+    }
+
+    #[test]
+    fn test_policy_round_trip_keeps_unknown_variants() {
+        let policy: Policy = serde_json::from_str(ASTRO_POLICY).unwrap();
+        let delegates = policy
+            .roles
+            .iter()
+            .find(|role| role.name == "delegates")
+            .unwrap();
+        assert!(matches!(delegates.kind, RoleKind::Other(_)));
+
+        let call_policy = &delegates.vote_policy["call"];
+        assert!(matches!(call_policy.weight_kind, WeightKind::Other(_)));
+        assert!(matches!(call_policy.threshold, WeightOrRatio::Other(_)));
+        assert_eq!(call_policy.extra["min_turnout"], json!("0"));
+    }
+
+    #[test]
+    fn test_policy_update_changes_only_edited_role() {
+        let mut policy: Policy = serde_json::from_str(ASTRO_POLICY).unwrap();
+        let summary = policy
+            .update_dao_members(
+                accounts(&["dave.near", "bob.near", "carol.near"]),
+                "goblins",
+            )
+            .unwrap();
+        assert_eq!(
+            summary,
+            MembershipSummary {
+                added: 2,
+                removed: 1,
+                unchanged: 1,
+            }
+        );
+
+        let expected = ASTRO_POLICY.trim_end().replace(
+            r#"{"Group":["zed.near","bob.near"]}"#,
+            r#"{"Group":["bob.near","carol.near","dave.near"]}"#,
+        );
+        assert_eq!(serde_json::to_string(&policy).unwrap(), expected);
+    }
+
+    #[test]
+    fn test_add_missing_role() {
+        let mut policy: Policy = serde_json::from_str(ASTRO_POLICY).unwrap();
+        let template: RoleTemplate = serde_json::from_value(json!({
+            "permissions": ["*:AddProposal", "*:VoteApprove"],
+            "vote_policy": {
+                "transfer": {
+                    "weight_kind": "RoleWeight",
+                    "quorum": "3",
+                    "threshold": [2, 3]
+                }
+            }
+        }))
+        .unwrap();
+
+        assert!(!policy.add_missing_role("goblins", &template));
+        assert!(policy.add_missing_role("holders", &template));
+        policy
+            .update_dao_members(accounts(&["bob.near"]), "holders")
+            .unwrap();
+
+        let policy = serde_json::to_value(&policy).unwrap();
+        assert_eq!(
+            policy["roles"][5],
+            json!({
+                "name": "holders",
+                "kind": { "Group": ["bob.near"] },
+                "permissions": ["*:AddProposal", "*:VoteApprove"],
+                "vote_policy": {
+                    "transfer": {
+                        "weight_kind": "RoleWeight",
+                        "quorum": "3",
+                        "threshold": [2, 3]
+                    }
+                }
+            })
+        );
+    }
+
+    #[test]
+    fn test_role_template_borsh_round_trip() {
+        let policy: Policy = serde_json::from_str(ASTRO_POLICY).unwrap();
+        let delegates = policy
+            .roles
+            .iter()
+            .find(|role| role.name == "delegates")
+            .unwrap();
+        let template = RoleTemplate {
+            permissions: delegates.permissions.iter().cloned().collect(),
+            vote_policy: delegates
+                .vote_policy
+                .iter()
+                .map(|(kind, vote_policy)| (kind.clone(), vote_policy.clone()))
+                .collect(),
+        };
+
+        let bytes = template.try_to_vec().unwrap();
+        let restored = RoleTemplate::try_from_slice(&bytes).unwrap();
+        assert_eq!(
+            serde_json::to_value(&restored).unwrap(),
+            serde_json::to_value(&template).unwrap()
+        );
+    }
 }
@@ -0,0 +1,130 @@
+//! Events emitted by the LinkDAO contract in the nep-297 format:
+//! <https://github.com/near/NEPs/blob/master/specs/Standards/EventsFormat.md>
+
+use near_sdk::json_types::U128;
+use near_sdk::serde::Serialize;
+use near_sdk::{env, serde_json};
+
+use crate::dao_structs::MembershipSummary;
+
+const EVENT_STANDARD: &str = "linkdao";
+const EVENT_VERSION: &str = "1.0.0";
+
+/// Logged when NFT owners already match the members of the DAO role and no proposal is submitted.
+#[must_use]
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MembersInSync<'a> {
+    pub link_id: &'a str,
+    pub dao_contract_id: &'a str,
+    pub role: &'a str,
+    pub members_count: u64,
+}
+
+impl MembersInSync<'_> {
+    pub fn emit(self) {
+        LinkDaoEventKind::MembersInSync(&self).emit()
+    }
+}
+
+/// Logged when every membership change of the DAO role is already proposed and its proposal can
+/// still be voted on, so no proposal is submitted.
+#[must_use]
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ChangesAlreadyProposed<'a> {
+    pub link_id: &'a str,
+    pub dao_contract_id: &'a str,
+    pub role: &'a str,
+    pub changes_count: u64,
+}
+
+impl ChangesAlreadyProposed<'_> {
+    pub fn emit(self) {
+        LinkDaoEventKind::ChangesAlreadyProposed(&self).emit()
+    }
+}
+
+/// Logged when a `ChangePolicy` proposal that updates members of the DAO role is submitted.
+#[must_use]
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct MembersUpdateProposed<'a> {
+    pub link_id: &'a str,
+    pub dao_contract_id: &'a str,
+    pub role: &'a str,
+    #[serde(flatten)]
+    pub summary: MembershipSummary,
+}
+
+impl MembersUpdateProposed<'_> {
+    pub fn emit(self) {
+        LinkDaoEventKind::MembersUpdateProposed(&self).emit()
+    }
+}
+
+/// Logged when DAO rejected submitted proposals and their bond is returned to the bond balance.
+#[must_use]
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposalsFailed<'a> {
+    pub link_id: &'a str,
+    pub bond: U128,
+}
+
+impl ProposalsFailed<'_> {
+    pub fn emit(self) {
+        LinkDaoEventKind::ProposalsFailed(&self).emit()
+    }
+}
+
+/// Logged when a sync fails. A sync in pages keeps its progress and the next call of
+/// `sync_dao_members` fetches the failed page, or DAO policy, again. Other syncs end and
+/// refund the caller bond.
+#[must_use]
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SyncFailed<'a> {
+    pub link_id: &'a str,
+    pub error: &'a str,
+}
+
+impl SyncFailed<'_> {
+    pub fn emit(self) {
+        LinkDaoEventKind::SyncFailed(&self).emit()
+    }
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+#[serde(tag = "event", content = "data")]
+#[serde(rename_all = "snake_case")]
+enum LinkDaoEventKind<'a> {
+    MembersInSync(&'a MembersInSync<'a>),
+    ChangesAlreadyProposed(&'a ChangesAlreadyProposed<'a>),
+    MembersUpdateProposed(&'a MembersUpdateProposed<'a>),
+    ProposalsFailed(&'a ProposalsFailed<'a>),
+    SyncFailed(&'a SyncFailed<'a>),
+}
+
+#[derive(Serialize)]
+#[serde(crate = "near_sdk::serde")]
+struct LinkDaoEvent<'a> {
+    standard: &'static str,
+    version: &'static str,
+    #[serde(flatten)]
+    event_kind: LinkDaoEventKind<'a>,
+}
+
+impl LinkDaoEventKind<'_> {
+    fn emit(self) {
+        let event = LinkDaoEvent {
+            standard: EVENT_STANDARD,
+            version: EVENT_VERSION,
+            event_kind: self,
+        };
+        // Events cannot fail to serialize so fine to panic on error
+        let json = serde_json::to_string(&event).unwrap_or_else(|_| env::abort());
+        env::log_str(&format!("EVENT_JSON:{}", json));
+    }
+}
@@ -0,0 +1,61 @@
+use near_sdk::serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use crate::*;
+
+/// Accounts that never become DAO members even if they own tokens, such as marketplaces and escrows.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Default, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Exclusions {
+    pub accounts: BTreeSet<AccountId>,
+    /// Account suffixes starting with a dot, e.g. `.paras.near` excludes every sub-account of `paras.near`.
+    pub suffixes: BTreeSet<String>,
+}
+
+impl Exclusions {
+    pub fn is_excluded(&self, account_id: &AccountId) -> bool {
+        self.accounts.contains(account_id)
+            || self
+                .suffixes
+                .iter()
+                .any(|suffix| account_id.as_str().ends_with(suffix.as_str()))
+    }
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Exclude accounts from DAO membership of every link.
+    pub fn add_exclusions(
+        &mut self,
+        accounts: Vec<AccountId>,
+        suffixes: Vec<String>,
+    ) -> Exclusions {
+        self.assert_owner();
+        require!(
+            suffixes.iter().all(|suffix| suffix.starts_with('.')),
+            "Suffix must start with a dot"
+        );
+        self.exclusions.accounts.extend(accounts);
+        self.exclusions.suffixes.extend(suffixes);
+        self.exclusions.clone()
+    }
+
+    pub fn remove_exclusions(
+        &mut self,
+        accounts: Vec<AccountId>,
+        suffixes: Vec<String>,
+    ) -> Exclusions {
+        self.assert_owner();
+        for account_id in &accounts {
+            self.exclusions.accounts.remove(account_id);
+        }
+        for suffix in &suffixes {
+            self.exclusions.suffixes.remove(suffix);
+        }
+        self.exclusions.clone()
+    }
+
+    pub fn get_exclusions(&self) -> Exclusions {
+        self.exclusions.clone()
+    }
+}
@@ -1,18 +1,38 @@
 mod dao_structs;
+mod events;
+mod exclusions;
+mod link;
+mod migrate;
+mod paged_sync;
 
 use dao_structs::*;
-use near_sdk::serde::Serialize;
-use near_sdk::serde_json::json;
-use std::collections::HashSet;
+use exclusions::Exclusions;
+use link::*;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{de::DeserializeOwned, Deserialize, Serialize};
+use near_sdk::serde_json::{json, Value};
+use paged_sync::{PagedMember, SyncProgress, SyncProgressView};
+use std::collections::{HashMap, HashSet};
 
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::{env, near_bindgen, AccountId, Gas, PanicOnDefault, Promise};
+use near_sdk::collections::{LookupMap, UnorderedMap};
+use near_sdk::{
+    env, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas, PanicOnDefault, Promise,
+    PromiseOrValue, PromiseResult,
+};
 
 const TGAS_GET_NFT_TOKENS: u64 = 20;
 const TGAS_GET_DAO_POLICY: u64 = 20;
 const TGAS_ADD_PROPOSAL: u64 = 40;
+const TGAS_ON_PROPOSALS_ADDED: u64 = 5;
+/// Gas kept aside in the sync callback for its own execution.
+const TGAS_SYNC_CALLBACK: u64 = 10;
+/// Gas kept aside in `sync_dao_members` for its own execution.
+const TGAS_SYNC_CALL: u64 = 10;
+/// 1 NEAR.
+const DEFAULT_OPERATING_RESERVE: Balance = 1_000_000_000_000_000_000_000_000;
 
-#[derive(BorshDeserialize, BorshSerialize, Clone, Serialize)]
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
 #[serde(crate = "near_sdk::serde")]
 pub enum MembershipType {
     Add,
@@ -23,84 +43,1835 @@ pub enum MembershipType {
 #[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
 pub struct Contract {
     owner_id: AccountId,
-    nft_contract_id: AccountId,
-    dao_contract_id: AccountId,
-    dao_owners_role: String,
+    links: UnorderedMap<LinkId, Link>,
+    /// NEAR available for proposal bonds of all links.
+    bond_balance: Balance,
+    /// Total amount of NEAR attached as proposal bonds and not returned yet.
+    bond_spent: Balance,
+    /// NEAR held for syncs in progress: caller bonds and bonds of proposals waiting for the DAO
+    /// to accept them. Kept apart from bonds the DAO returns when reclaiming them.
+    held_bonds: Balance,
+    /// NEAR kept on the account to pay for storage of new links and syncs in pages, never counted
+    /// as returned bonds.
+    operating_reserve: Balance,
+    /// Accounts filtered out of NFT owners of every link.
+    exclusions: Exclusions,
+    /// Owners collected by syncs in pages that are in progress.
+    sync_progress: LookupMap<LinkId, SyncProgress>,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Links,
+    SyncProgress,
+    SyncOwners { link_hash: Vec<u8> },
+    SyncMembers { link_hash: Vec<u8> },
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct BondInfo {
+    pub balance: U128,
+    pub spent: U128,
+    pub operating_reserve: U128,
 }
 
 #[near_bindgen]
 impl Contract {
     #[init]
-    pub fn new(
-        owner_id: AccountId,
-        nft_contract_id: AccountId,
-        dao_contract_id: AccountId,
-    ) -> Self {
+    pub fn new(owner_id: AccountId) -> Self {
         assert!(!env::state_exists(), "Already initialized");
         Self {
             owner_id,
-            nft_contract_id,
-            dao_contract_id,
-            dao_owners_role: String::new(),
+            links: UnorderedMap::new(StorageKey::Links),
+            bond_balance: 0,
+            bond_spent: 0,
+            held_bonds: 0,
+            operating_reserve: DEFAULT_OPERATING_RESERVE,
+            exclusions: Exclusions::default(),
+            sync_progress: LookupMap::new(StorageKey::SyncProgress),
         }
     }
 
-    /// Synchronize NFT owners and DAO members.
+    /// Synchronize NFT owners and DAO members of the link.
     /// Replace existing members with current NFT owners.
-    pub fn sync_dao_members(&mut self) -> Promise {
-        self.assert_owner();
+    ///
+    /// Anyone can call it once `sync_settings.min_interval` of the link has passed since its last
+    /// successful sync, attaching at least `sync_settings.caller_bond`. The owner can call it at any
+    /// time. A new sync can't start until the previous one finishes, and every call needs enough
+    /// gas for its fetches and for a callback able to submit a proposal.
+    ///
+    /// Owners of a link with `page_size` are fetched one page per call. Anyone can continue a sync
+    /// in progress without a deposit once the previous page has arrived. After the last page, calls
+    /// resolve collected owners into members a page at a time and return the sync progress;
+    /// the call after that submits proposals.
+    /// Collections that don't implement the owners views are synced in pages of NEP-181 `nft_tokens`.
+    #[payable]
+    pub fn sync_dao_members(&mut self, link_id: LinkId) -> PromiseOrValue<SyncProgressView> {
+        let mut link = self.expect_link(&link_id);
+        let caller_id = env::predecessor_account_id();
+        let caller_bond = env::attached_deposit();
+        if self.sync_progress.get(&link_id).is_some() {
+            require!(caller_bond == 0, "Sync is already in progress");
+            assert_sync_gas(1);
+            return self.continue_sync(link_id, link);
+        }
+        require!(!link.syncing, "Sync is already in progress");
+        if link.page_size.is_some() {
+            assert_sync_gas(1);
+        } else {
+            let holders_since_fetches = if link.needs_holders_since() { 2 } else { 1 };
+            assert_sync_gas(link.nft_contract_ids.len() as u64 * holders_since_fetches + 1);
+        }
+        if caller_id != self.owner_id {
+            require!(
+                caller_bond >= link.sync_settings.caller_bond.0,
+                format!(
+                    "Requires attached deposit of at least {} yoctoNEAR",
+                    link.sync_settings.caller_bond.0
+                )
+            );
+            if let Some(last_sync_at) = link.last_sync_at {
+                let next_sync_at = last_sync_at.0 + link.sync_settings.min_interval.0;
+                require!(
+                    env::block_timestamp() >= next_sync_at,
+                    format!("Next sync is allowed after {}", next_sync_at)
+                );
+            }
+        }
+        link.syncing = true;
+        self.links.insert(&link_id, &link);
+        self.held_bonds += caller_bond;
 
-        let ext_self = Self::ext(env::current_account_id());
+        if link.page_size.is_some() {
+            return PromiseOrValue::Promise(self.sync_next_page(
+                link_id,
+                link,
+                caller_id,
+                caller_bond,
+            ));
+        }
+
+        let owners_view = link.owners_view();
         let gas_get_owners = Gas::ONE_TERA * TGAS_GET_NFT_TOKENS;
         let gas_get_policy = Gas::ONE_TERA * TGAS_GET_DAO_POLICY;
 
-        Promise::new(self.nft_contract_id.clone())
-            .function_call("nft_owners".into(), vec![], 0, gas_get_owners)
-            .and(Promise::new(self.dao_contract_id.clone()).function_call(
-                "get_policy".into(),
-                vec![],
-                0,
-                gas_get_policy,
-            ))
-            .then(ext_self.handle_dao_members_sync())
+        // Owners of every collection, then holding times of every collection if needed, and DAO
+        // policy are fetched in parallel. Results come to the callback in the same order, policy
+        // being the last one.
+        let mut calls: Vec<(AccountId, &str, Vec<u8>)> = link
+            .nft_contract_ids
+            .iter()
+            .map(|id| (id.clone(), owners_view.method_name(), owners_view.args()))
+            .collect();
+        if link.needs_holders_since() {
+            calls.extend(
+                link.nft_contract_ids
+                    .iter()
+                    .map(|id| (id.clone(), "nft_holders_since", vec![])),
+            );
+        }
+        let get_policy = Promise::new(link.dao_contract_id).function_call(
+            "get_policy".into(),
+            vec![],
+            0,
+            gas_get_policy,
+        );
+        let promise = calls
+            .into_iter()
+            .rev()
+            .fold(
+                get_policy,
+                |promise, (nft_contract_id, method_name, args)| {
+                    Promise::new(nft_contract_id)
+                        .function_call(method_name.into(), args, 0, gas_get_owners)
+                        .and(promise)
+                },
+            )
+            .then(
+                Self::ext(env::current_account_id()).handle_dao_members_sync(
+                    link_id,
+                    caller_id,
+                    U128(caller_bond),
+                ),
+            );
+        PromiseOrValue::Promise(promise)
     }
 
+    /// Returns id of the last submitted proposal or `null` if DAO members already match NFT owners.
+    ///
+    /// A sync that fails, e.g. when the DAO policy can't be fetched or the bond balance is too low,
+    /// returns `null` too and refunds the caller bond. A sync in pages keeps its collected owners
+    /// instead, the next call of `sync_dao_members` fetches DAO policy again.
     #[private]
     pub fn handle_dao_members_sync(
         &mut self,
-        #[callback] owners: HashSet<AccountId>,
-        #[callback] mut policy: Policy,
-    ) -> Promise {
-        policy.update_dao_members(owners, self.dao_owners_role.clone());
+        link_id: LinkId,
+        caller_id: AccountId,
+        caller_bond: U128,
+    ) -> PromiseOrValue<Option<u64>> {
+        match self.try_handle_dao_members_sync(&link_id, caller_id.clone(), caller_bond) {
+            Ok(result) => result,
+            Err(error) => {
+                events::SyncFailed {
+                    link_id: &link_id,
+                    error: &error,
+                }
+                .emit();
+                match self.sync_progress.get(&link_id) {
+                    Some(mut progress) => {
+                        progress.fetching = false;
+                        self.sync_progress.insert(&link_id, &progress);
+                    }
+                    None => {
+                        self.finish_sync(&link_id, false);
+                        if caller_bond.0 > 0 {
+                            self.release_held_bonds(caller_bond.0);
+                            Promise::new(caller_id).transfer(caller_bond.0);
+                        }
+                    }
+                }
+                PromiseOrValue::Value(None)
+            }
+        }
+    }
 
-        let gas = Gas::ONE_TERA * TGAS_ADD_PROPOSAL;
-        let args = json!({
-          "proposal": {
-            "description": "Update DAO members",
-            "kind": {
-                "ChangePolicy": {
-                    "policy": policy
-              }
+    /// Returns id of the last added proposal and refunds the caller bond.
+    /// If proposals were rejected by DAO, attached bonds go back to `bond_balance`, otherwise
+    /// `proposed_changes` are skipped by syncs until their proposals expire.
+    #[private]
+    pub fn on_proposals_added(
+        &mut self,
+        link_id: LinkId,
+        bond: U128,
+        caller_id: AccountId,
+        caller_bond: U128,
+        proposed_changes: Vec<ProposedChange>,
+    ) -> Option<u64> {
+        self.release_held_bonds(bond.0 + caller_bond.0);
+        let result = env::promise_result(0);
+        let succeeded = matches!(result, PromiseResult::Successful(_));
+        if let Some(mut link) = self.links.get(&link_id).filter(|_| succeeded) {
+            link.proposed_changes.extend(proposed_changes);
+            self.links.insert(&link_id, &link);
+        }
+        self.finish_sync(&link_id, succeeded);
+        if caller_bond.0 > 0 {
+            Promise::new(caller_id).transfer(caller_bond.0);
+        }
+        match result {
+            PromiseResult::Successful(value) => near_sdk::serde_json::from_slice(&value).ok(),
+            _ => {
+                self.bond_balance += bond.0;
+                // Bonds reclaimed with `reclaim_bonds` may leave less spent than `bond`
+                self.bond_spent = self.bond_spent.saturating_sub(bond.0);
+                events::ProposalsFailed {
+                    link_id: &link_id,
+                    bond,
+                }
+                .emit();
+                None
             }
-          }
-        })
-        .to_string()
-        .into_bytes();
+        }
+    }
 
-        Promise::new(self.dao_contract_id.clone()).function_call(
-            "add_proposal".into(),
-            args,
-            0,
-            gas,
-        )
+    /// Top up NEAR balance used to pay DAO proposal bonds.
+    #[payable]
+    pub fn deposit_bond(&mut self) -> BondInfo {
+        self.bond_balance += env::attached_deposit();
+        self.get_bond_info()
+    }
+
+    pub fn withdraw_bond(&mut self, amount: U128) -> Promise {
+        self.assert_owner();
+        require!(amount.0 <= self.bond_balance, "Not enough bond balance");
+        self.bond_balance -= amount.0;
+        Promise::new(self.owner_id.clone()).transfer(amount.0)
     }
 
-    pub fn set_dao_role(&mut self, role: String) {
+    /// Add proposal bonds returned by DAOs to the bond balance and return the updated bond info.
+    ///
+    /// DAOs return bonds straight to the account once proposals are finalized, so returned bonds
+    /// are the part of the account balance not taken by storage, the operating reserve, the bond
+    /// balance or held bonds, at most `bond_spent`.
+    pub fn reclaim_bonds(&mut self) -> BondInfo {
         self.assert_owner();
-        self.dao_owners_role = role;
+        let storage_cost = env::storage_usage() as Balance * env::storage_byte_cost();
+        let kept = storage_cost + self.operating_reserve + self.bond_balance + self.held_bonds;
+        let returned = env::account_balance()
+            .saturating_sub(kept)
+            .min(self.bond_spent);
+        self.bond_balance += returned;
+        self.bond_spent -= returned;
+        self.get_bond_info()
+    }
+
+    /// Set the NEAR kept on the account for its own storage, which `reclaim_bonds` leaves out.
+    pub fn set_operating_reserve(&mut self, amount: U128) -> BondInfo {
+        self.assert_owner();
+        self.operating_reserve = amount.0;
+        self.get_bond_info()
+    }
+
+    pub fn get_bond_info(&self) -> BondInfo {
+        BondInfo {
+            balance: U128(self.bond_balance),
+            spent: U128(self.bond_spent),
+            operating_reserve: U128(self.operating_reserve),
+        }
     }
 
     fn assert_owner(&self) {
         assert_eq!(self.owner_id, env::predecessor_account_id());
     }
 }
+
+impl Contract {
+    /// Stop holding `amount` once a caller bond or proposal bonds are refunded or spent.
+    fn release_held_bonds(&mut self, amount: Balance) {
+        self.held_bonds = self.held_bonds.saturating_sub(amount);
+    }
+
+    /// Let the link be synced and changed again once its sync ends. Only a sync that `succeeded`
+    /// starts the interval before the next one.
+    pub(crate) fn finish_sync(&mut self, link_id: &LinkId, succeeded: bool) {
+        if let Some(mut link) = self.links.get(link_id) {
+            link.syncing = false;
+            if succeeded {
+                link.last_sync_at = Some(env::block_timestamp().into());
+            }
+            self.links.insert(link_id, &link);
+        }
+    }
+}
+
+impl Contract {
+    /// Body of `handle_dao_members_sync`. Errors are returned before any state is changed.
+    fn try_handle_dao_members_sync(
+        &mut self,
+        link_id: &LinkId,
+        caller_id: AccountId,
+        caller_bond: U128,
+    ) -> Result<PromiseOrValue<Option<u64>>, String> {
+        let mut link = self.links.get(link_id).ok_or("Link not found")?;
+        // The sync was cancelled while DAO policy was being fetched.
+        if !link.syncing {
+            return Ok(PromiseOrValue::Value(None));
+        }
+
+        // Owners of a link synced in pages are already collected, only the policy is fetched.
+        let paged_owners = match self.sync_progress.get(link_id) {
+            // The sync was cancelled while DAO policy was being fetched.
+            Some(progress) if !progress.fetching || !progress.owners.is_empty() => {
+                return Ok(PromiseOrValue::Value(None));
+            }
+            Some(progress) => Some(progress.members.iter().flatten().collect::<Vec<_>>()),
+            None => None,
+        };
+        // Owners collected in pages come with the time since which they hold tokens.
+        let paged_holders_since: Option<HashMap<AccountId, u64>> = match &paged_owners {
+            Some(members) if link.needs_holders_since() => Some(
+                members
+                    .iter()
+                    .filter_map(|(_, owner_id, _, since)| Some((owner_id.clone(), (*since)?)))
+                    .collect(),
+            ),
+            _ => None,
+        };
+        let collections = match paged_owners {
+            Some(_) => 0,
+            None => link.nft_contract_ids.len() as u64,
+        };
+        let holders_since_results = if link.needs_holders_since() {
+            collections
+        } else {
+            0
+        };
+        let policy_index = collections + holders_since_results;
+        if env::promise_results_count() != policy_index + 1 {
+            return Err("Unexpected number of promise results".to_string());
+        }
+        let owners_view = link.owners_view();
+        // Collections that don't implement the owners views are counted from NEP-181
+        // `nft_tokens` instead, page by page.
+        let owners_failed = (0..collections)
+            .any(|index| matches!(env::promise_result(index), PromiseResult::Failed));
+        if owners_failed && owners_view.supports_enumeration() && holders_since_results == 0 {
+            return Ok(PromiseOrValue::Promise(self.sync_next_page(
+                link_id.clone(),
+                link,
+                caller_id,
+                caller_bond.0,
+            )));
+        }
+        let mut members_per_role = match (paged_owners, &owners_view) {
+            (Some(members), OwnersView::OwnersByAttribute(_)) => {
+                link.paged_attribute_role_members(members)
+            }
+            (Some(members), _) => {
+                let owners = members
+                    .into_iter()
+                    .map(|(_, owner_id, tokens, _)| (owner_id, tokens))
+                    .collect();
+                link.role_members(&owners)
+            }
+            (None, OwnersView::OwnersByAttribute(_)) => {
+                let owners_per_collection = (0..collections)
+                    .map(|index| parse_promise_result(index, "Failed to get NFT owners"))
+                    .collect::<Result<_, _>>()?;
+                link.attribute_role_members(owners_per_collection)
+            }
+            (None, _) => {
+                let owners_per_collection = (0..collections)
+                    .map(|index| owner_counts(index, &owners_view))
+                    .collect::<Result<_, _>>()?;
+                link.role_members(&link.membership_rule.combine(owners_per_collection))
+            }
+        };
+        for (_, members) in members_per_role.iter_mut() {
+            members.retain(|account_id| !self.exclusions.is_excluded(account_id));
+        }
+        let mut policy: Policy = parse_promise_result(policy_index, "Failed to get DAO policy")?;
+
+        // A missing role can only be created with `ChangePolicy`, members are set in the same proposal.
+        let mut role_added = false;
+        for (role, _) in &members_per_role {
+            role_added |= policy.add_missing_role(role, &link.role_template);
+        }
+
+        // Owners that became holders in several collections count from the earliest one.
+        let mut holders_since = paged_holders_since;
+        if holders_since_results > 0 {
+            let mut earliest_since: HashMap<AccountId, u64> = HashMap::new();
+            for index in collections..policy_index {
+                let since_per_owner: HashMap<AccountId, U64> =
+                    parse_promise_result(index, "Failed to get NFT holders since")?;
+                for (owner_id, since) in since_per_owner {
+                    let entry = earliest_since.entry(owner_id).or_insert(since.0);
+                    *entry = (*entry).min(since.0);
+                }
+            }
+            holders_since = Some(earliest_since);
+        }
+        link.apply_holding_settings(&mut members_per_role, &policy, holders_since.as_ref())?;
+        link.merge_mode.apply(&mut members_per_role, &policy)?;
+
+        let mut changes: Vec<_> = members_per_role
+            .iter()
+            .map(|(role, members)| policy.membership_changes(members, role))
+            .collect::<Result<_, _>>()?;
+        if !role_added && changes.iter().all(Vec::is_empty) {
+            for (role, members) in &members_per_role {
+                events::MembersInSync {
+                    link_id,
+                    dao_contract_id: link.dao_contract_id.as_str(),
+                    role,
+                    members_count: members.len() as u64,
+                }
+                .emit();
+            }
+            self.links.insert(link_id, &link);
+            self.finish_sync(link_id, true);
+            self.remove_paged_sync(link_id);
+            // Nothing has changed, so the caller bond is kept to pay for future proposals.
+            self.release_held_bonds(caller_bond.0);
+            self.bond_balance += caller_bond.0;
+            return Ok(PromiseOrValue::Value(None));
+        }
+
+        let proposal_bond = policy.proposal_bond.0;
+        let mut summaries: Vec<(String, MembershipSummary)> = vec![];
+        let mut proposed_changes: Vec<ProposedChange> = vec![];
+        let proposals = match link.proposal_mode {
+            ProposalMode::MemberChanges if !role_added && members_per_role.len() == 1 => {
+                let role = &members_per_role[0].0;
+                let changes: Vec<_> = link
+                    .unproposed_changes(role, changes.remove(0), policy.proposal_period.0)
+                    .into_iter()
+                    .take(max_member_proposals()?)
+                    .collect();
+                if changes.is_empty() {
+                    events::ChangesAlreadyProposed {
+                        link_id,
+                        dao_contract_id: link.dao_contract_id.as_str(),
+                        role,
+                        changes_count: link.proposed_changes.len() as u64,
+                    }
+                    .emit();
+                    self.links.insert(link_id, &link);
+                    self.finish_sync(link_id, true);
+                    self.remove_paged_sync(link_id);
+                    if caller_bond.0 > 0 {
+                        self.release_held_bonds(caller_bond.0);
+                        Promise::new(caller_id).transfer(caller_bond.0);
+                    }
+                    return Ok(PromiseOrValue::Value(None));
+                }
+                proposed_changes = changes
+                    .iter()
+                    .map(|(account_id, membership_type)| ProposedChange {
+                        role: role.clone(),
+                        account_id: account_id.clone(),
+                        membership_type: membership_type.clone(),
+                        proposed_at: env::block_timestamp().into(),
+                    })
+                    .collect();
+                member_proposals(role, changes, &link.merge_mode.description())
+            }
+            _ => {
+                summaries = members_per_role
+                    .into_iter()
+                    .map(|(role, members)| {
+                        let summary = policy.update_dao_members(members, &role)?;
+                        Ok((role, summary))
+                    })
+                    .collect::<Result<_, String>>()?;
+                vec![change_policy_proposal(
+                    policy,
+                    &summaries,
+                    &link.merge_mode.description(),
+                )]
+            }
+        };
+        let promise = self.add_proposals(
+            link_id,
+            &link.dao_contract_id,
+            proposals,
+            proposal_bond,
+            caller_id,
+            caller_bond,
+            proposed_changes,
+        )?;
+        for (role, summary) in summaries {
+            events::MembersUpdateProposed {
+                link_id,
+                dao_contract_id: link.dao_contract_id.as_str(),
+                role: &role,
+                summary,
+            }
+            .emit();
+        }
+        self.links.insert(link_id, &link);
+        self.remove_paged_sync(link_id);
+        Ok(PromiseOrValue::Promise(promise))
+    }
+
+    /// Submit `proposals` to DAO in a single batch, attaching `proposal_bond` to each of them.
+    /// `proposed_changes` are recorded on the link once DAO accepts the proposals.
+    #[allow(clippy::too_many_arguments)]
+    fn add_proposals(
+        &mut self,
+        link_id: &LinkId,
+        dao_contract_id: &AccountId,
+        proposals: Vec<Value>,
+        proposal_bond: Balance,
+        caller_id: AccountId,
+        caller_bond: U128,
+        proposed_changes: Vec<ProposedChange>,
+    ) -> Result<Promise, String> {
+        let total_bond = proposal_bond * proposals.len() as Balance;
+        if total_bond > self.bond_balance {
+            return Err(format!(
+                "Not enough bond balance to add {} proposals: {} required, {} available",
+                proposals.len(),
+                total_bond,
+                self.bond_balance
+            ));
+        }
+        self.bond_balance -= total_bond;
+        self.bond_spent += total_bond;
+        self.held_bonds += total_bond;
+
+        let gas = Gas::ONE_TERA * TGAS_ADD_PROPOSAL;
+        let mut promise = Promise::new(dao_contract_id.clone());
+        for proposal in proposals {
+            let args = json!({ "proposal": proposal }).to_string().into_bytes();
+            promise = promise.function_call("add_proposal".into(), args, proposal_bond, gas);
+        }
+
+        Ok(promise.then(
+            Self::ext(env::current_account_id())
+                .with_static_gas(Gas::ONE_TERA * TGAS_ON_PROPOSALS_ADDED)
+                .on_proposals_added(
+                    link_id.clone(),
+                    U128(total_bond),
+                    caller_id,
+                    caller_bond,
+                    proposed_changes,
+                ),
+        ))
+    }
+}
+
+/// Require enough prepaid gas for `fetches` calls to NFT and DAO contracts and a sync callback able
+/// to submit a proposal, so a caller can't make the sync fail for lack of gas.
+fn assert_sync_gas(fetches: u64) {
+    let tgas = TGAS_GET_NFT_TOKENS.max(TGAS_GET_DAO_POLICY) * fetches
+        + TGAS_SYNC_CALL
+        + TGAS_SYNC_CALLBACK
+        + TGAS_ADD_PROPOSAL
+        + TGAS_ON_PROPOSALS_ADDED;
+    require!(
+        env::prepaid_gas() >= Gas::ONE_TERA * tgas,
+        format!("Requires at least {} TGas", tgas)
+    );
+}
+
+/// Promise result `index` parsed from JSON, or `error` if the promise failed or returned
+/// something else.
+fn parse_promise_result<T: DeserializeOwned>(index: u64, error: &str) -> Result<T, String> {
+    match env::promise_result(index) {
+        PromiseResult::Successful(value) => near_sdk::serde_json::from_slice(&value)
+            .map_err(|_| format!("{}: invalid response", error)),
+        _ => Err(error.to_string()),
+    }
+}
+
+/// Read owners of a collection with the number of tokens they hold from promise result `index`.
+/// Owners fetched without counts hold a single token.
+fn owner_counts(index: u64, owners_view: &OwnersView) -> Result<HashMap<AccountId, u64>, String> {
+    let error = "Failed to get NFT owners";
+    let counts: Vec<(AccountId, U128)> = match owners_view {
+        OwnersView::Owners => {
+            let owners: HashSet<AccountId> = parse_promise_result(index, error)?;
+            return Ok(owners.into_iter().map(|owner_id| (owner_id, 1)).collect());
+        }
+        OwnersView::SnapshotHolders(_) => parse_promise_result(index, error)?,
+        _ => parse_promise_result::<HashMap<AccountId, U128>>(index, error)?
+            .into_iter()
+            .collect(),
+    };
+    Ok(counts
+        .into_iter()
+        .map(|(owner_id, count)| (owner_id, count.0 as u64))
+        .collect())
+}
+
+/// `merge_note` explains how the sync treats existing members.
+fn change_policy_proposal(
+    policy: Policy,
+    summaries: &[(String, MembershipSummary)],
+    merge_note: &str,
+) -> Value {
+    let changes = summaries
+        .iter()
+        .map(|(role, summary)| {
+            format!(
+                "Update {} members: {} added, {} removed, {} unchanged",
+                role, summary.added, summary.removed, summary.unchanged
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ");
+    let description = format!("{}. {}", changes, merge_note);
+    json!({
+        "description": description,
+        "kind": {
+            "ChangePolicy": {
+                "policy": policy
+            }
+        }
+    })
+}
+
+/// Number of proposals the remaining gas can submit.
+/// Changes that don't fit are left for the next sync.
+fn max_member_proposals() -> Result<usize, String> {
+    let gas = Gas::ONE_TERA * TGAS_ADD_PROPOSAL;
+    let reserved_gas = Gas::ONE_TERA * (TGAS_SYNC_CALLBACK + TGAS_ON_PROPOSALS_ADDED);
+    let available_gas = (env::prepaid_gas() - env::used_gas())
+        .0
+        .saturating_sub(reserved_gas.0);
+    let max_proposals = (available_gas / gas.0) as usize;
+    if max_proposals == 0 {
+        return Err("Not enough gas to add proposals".to_string());
+    }
+    Ok(max_proposals)
+}
+
+/// Build a proposal for every membership change.
+fn member_proposals(
+    role: &str,
+    changes: Vec<(AccountId, MembershipType)>,
+    merge_note: &str,
+) -> Vec<Value> {
+    changes
+        .into_iter()
+        .map(|(member_id, membership_type)| {
+            let (description, kind) = match membership_type {
+                MembershipType::Add => (
+                    format!("Add {} to {}. {}", member_id, role, merge_note),
+                    "AddMemberToRole",
+                ),
+                MembershipType::Remove => (
+                    format!("Remove {} from {}. {}", member_id, role, merge_note),
+                    "RemoveMemberFromRole",
+                ),
+            };
+            json!({
+                "description": description,
+                "kind": {
+                    kind: {
+                        "member_id": member_id,
+                        "role": role
+                    }
+                }
+            })
+        })
+        .collect()
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::serde_json;
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
+    use near_sdk::{testing_env, RuntimeFeesConfig, VMConfig, VMContext};
+
+    use super::*;
+
+    const LINK_ID: &str = "goblins";
+    const PROPOSAL_BOND: Balance = 100_000_000_000_000_000_000_000;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    fn sample_policy(members: &[AccountId]) -> Policy {
+        sample_policy_with_bond(members, 0)
+    }
+
+    fn sample_policy_with_bond(members: &[AccountId], proposal_bond: Balance) -> Policy {
+        serde_json::from_value(json!({
+            "roles": [{
+                "name": "holders",
+                "kind": { "Group": members },
+                "permissions": ["*:AddProposal"],
+                "vote_policy": {}
+            }],
+            "default_vote_policy": {
+                "weight_kind": "RoleWeight",
+                "quorum": "0",
+                "threshold": [1, 2]
+            },
+            "proposal_bond": proposal_bond.to_string(),
+            "proposal_period": "604800000000000",
+            "bounty_bond": "0",
+            "bounty_forgiveness_period": "86400000000000"
+        }))
+        .unwrap()
+    }
+
+    fn setup_contract() -> Contract {
+        testing_env!(get_context(accounts(1)).build());
+        let mut contract = Contract::new(accounts(1));
+        contract.add_link(LINK_ID.into(), vec![accounts(2)], accounts(3), None);
+        contract
+    }
+
+    fn success(value: &impl Serialize) -> PromiseResult {
+        PromiseResult::Successful(serde_json::to_vec(value).unwrap())
+    }
+
+    /// Call the sync callback as if `owners_per_collection` and `policy` were returned
+    /// by NFT and DAO contracts.
+    fn handle_sync(
+        contract: &mut Contract,
+        context: VMContext,
+        link_id: &str,
+        caller_bond: Balance,
+        owners_per_collection: Vec<Vec<AccountId>>,
+        policy: Policy,
+    ) -> PromiseOrValue<Option<u64>> {
+        let results = owners_per_collection.iter().map(success).collect();
+        handle_sync_results(contract, context, link_id, caller_bond, results, policy)
+    }
+
+    fn handle_sync_results(
+        contract: &mut Contract,
+        context: VMContext,
+        link_id: &str,
+        caller_bond: Balance,
+        mut results: Vec<PromiseResult>,
+        policy: Policy,
+    ) -> PromiseOrValue<Option<u64>> {
+        results.push(success(&policy));
+        start_sync(contract, link_id);
+        testing_env!(
+            context,
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            results,
+        );
+        contract.handle_dao_members_sync(link_id.into(), accounts(1), U128(caller_bond))
+    }
+
+    /// Mark the link as synced, as `sync_dao_members` does before fetching owners and policy.
+    fn start_sync(contract: &mut Contract, link_id: &str) {
+        let mut link = contract.expect_link(&link_id.into());
+        link.syncing = true;
+        contract.links.insert(&link_id.into(), &link);
+    }
+
+    /// Call the callback of proposals submitted without bonds as if DAO returned `result`.
+    fn on_proposals_added(
+        contract: &mut Contract,
+        link_id: &str,
+        result: PromiseResult,
+        proposed_changes: Vec<ProposedChange>,
+    ) -> Option<u64> {
+        testing_env!(
+            get_context(accounts(0)).block_timestamp(42).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+        contract.on_proposals_added(
+            link_id.into(),
+            U128(0),
+            accounts(1),
+            U128(0),
+            proposed_changes,
+        )
+    }
+
+    fn on_owners_page(contract: &mut Contract, result: PromiseResult) -> SyncProgressView {
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![result],
+        );
+        let page = contract
+            .sync_progress
+            .get(&LINK_ID.into())
+            .unwrap()
+            .requested_pages;
+        contract.on_owners_page(LINK_ID.into(), U64(page))
+    }
+
+    /// Resolve owners collected by a sync in pages until it fetches DAO policy.
+    fn resolve_paged_owners(contract: &mut Contract) {
+        testing_env!(get_context(accounts(0)).build());
+        while let PromiseOrValue::Value(_) = contract.sync_dao_members(LINK_ID.into()) {}
+    }
+
+    fn owner_set(owners: &[AccountId]) -> HashSet<AccountId> {
+        owners.iter().cloned().collect()
+    }
+
+    fn token_counts(owners: &[(AccountId, u64)]) -> HashMap<AccountId, u64> {
+        owners.iter().cloned().collect()
+    }
+
+    fn sample_tiers(mode: TierMode) -> RoleAssignment {
+        RoleAssignment::Tiers {
+            tiers: vec![
+                Tier {
+                    role: "holders".into(),
+                    min_tokens: 1,
+                },
+                Tier {
+                    role: "whales".into(),
+                    min_tokens: 10,
+                },
+                Tier {
+                    role: "council".into(),
+                    min_tokens: 25,
+                },
+            ],
+            mode,
+        }
+    }
+
+    #[test]
+    fn test_sync_skipped_when_members_match() {
+        let mut contract = setup_contract();
+        let result = handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4), accounts(5)]],
+            sample_policy(&[accounts(5), accounts(4)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        assert_eq!(get_logs().len(), 1);
+        assert!(get_logs()[0].contains("\"event\":\"members_in_sync\""));
+    }
+
+    #[test]
+    fn test_sync_submits_proposals_when_members_differ() {
+        let mut contract = setup_contract();
+        let result = handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4)]],
+            sample_policy(&[accounts(5)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(get_logs().is_empty());
+    }
+
+    #[test]
+    fn test_open_proposals_are_not_submitted_again() {
+        let mut contract = setup_contract();
+        // Gas for a single proposal per sync
+        let sync = |contract: &mut Contract, block_timestamp| {
+            handle_sync(
+                contract,
+                get_context(accounts(0))
+                    .block_timestamp(block_timestamp)
+                    .prepaid_gas(Gas::ONE_TERA * 90)
+                    .build(),
+                LINK_ID,
+                0,
+                vec![vec![accounts(4), accounts(5)]],
+                sample_policy(&[]),
+            )
+        };
+        let proposed = |account_id: AccountId, proposed_at: u64| ProposedChange {
+            role: "holders".into(),
+            account_id,
+            membership_type: MembershipType::Add,
+            proposed_at: U64(proposed_at),
+        };
+
+        assert!(matches!(sync(&mut contract, 1), PromiseOrValue::Promise(_)));
+        on_proposals_added(
+            &mut contract,
+            LINK_ID,
+            success(&7),
+            vec![proposed(accounts(4), 1)],
+        );
+        // The next sync proposes the change that didn't fit
+        assert!(matches!(sync(&mut contract, 2), PromiseOrValue::Promise(_)));
+        assert_eq!(
+            contract.get_link(LINK_ID.into()).unwrap().proposed_changes,
+            vec![proposed(accounts(4), 1)]
+        );
+        on_proposals_added(
+            &mut contract,
+            LINK_ID,
+            success(&8),
+            vec![proposed(accounts(5), 2)],
+        );
+
+        let result = sync(&mut contract, 3);
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        let logs = get_logs();
+        assert!(logs[0].contains("\"event\":\"changes_already_proposed\""));
+        assert!(logs[0].contains("\"changes_count\":2"));
+
+        // Once its proposal expires, a change is proposed again
+        let proposal_period = sample_policy(&[]).proposal_period.0;
+        let result = sync(&mut contract, 1 + proposal_period);
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert_eq!(
+            contract.get_link(LINK_ID.into()).unwrap().proposed_changes,
+            vec![proposed(accounts(5), 2)]
+        );
+    }
+
+    #[test]
+    fn test_sync_adds_missing_role() {
+        let mut contract = setup_contract();
+        contract.set_dao_role(LINK_ID.into(), "goblins".into());
+        contract.set_proposal_mode(LINK_ID.into(), ProposalMode::MemberChanges);
+
+        let result = handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4)]],
+            sample_policy(&[accounts(5)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(get_logs()[0].contains("\"event\":\"members_update_proposed\""));
+        assert!(get_logs()[0].contains("\"role\":\"goblins\",\"added\":1"));
+    }
+
+    #[test]
+    fn test_sync_attaches_proposal_bond() {
+        let mut contract = setup_contract();
+        testing_env!(get_context(accounts(1))
+            .attached_deposit(PROPOSAL_BOND * 3)
+            .build());
+        contract.deposit_bond();
+
+        handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4)]],
+            sample_policy_with_bond(&[accounts(5)], PROPOSAL_BOND),
+        );
+
+        let bond_info = contract.get_bond_info();
+        assert_eq!(bond_info.balance.0, PROPOSAL_BOND);
+        assert_eq!(bond_info.spent.0, PROPOSAL_BOND * 2);
+    }
+
+    #[test]
+    fn test_failed_sync_returns_caller_bond() {
+        let mut contract = setup_contract();
+        let caller_bond = SyncSettings::default().caller_bond.0;
+
+        // Not enough bond balance for the proposal
+        let result = handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            caller_bond,
+            vec![vec![accounts(4)]],
+            sample_policy_with_bond(&[], PROPOSAL_BOND),
+        );
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        let logs = get_logs();
+        assert_eq!(logs.len(), 1);
+        assert!(logs[0].contains("\"event\":\"sync_failed\""));
+        assert!(logs[0].contains("Not enough bond balance"));
+        assert_eq!(contract.get_bond_info().balance.0, 0);
+
+        let link = contract.get_link(LINK_ID.into()).unwrap();
+        assert!(!link.syncing);
+        assert_eq!(link.last_sync_at, None);
+
+        // DAO policy can't be fetched
+        start_sync(&mut contract, LINK_ID);
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![success(&vec![accounts(4)]), PromiseResult::Failed],
+        );
+        let result =
+            contract.handle_dao_members_sync(LINK_ID.into(), accounts(1), U128(caller_bond));
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        assert!(get_logs()[0].contains("Failed to get DAO policy"));
+        assert_eq!(contract.get_bond_info().balance.0, 0);
+
+        // The role of the link is not a group
+        let mut policy = sample_policy(&[]);
+        policy.roles[0].kind = RoleKind::Everyone;
+        let result = handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            caller_bond,
+            vec![vec![accounts(4)]],
+            policy,
+        );
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        assert!(get_logs()[0].contains("Role holders of DAO policy is not a group"));
+        assert!(!contract.get_link(LINK_ID.into()).unwrap().syncing);
+        assert_eq!(contract.held_bonds, 0);
+    }
+
+    #[test]
+    fn test_failed_paged_sync_keeps_owners() {
+        let mut contract = setup_contract();
+        contract.set_page_size(LINK_ID.into(), Some(U64(2)));
+        contract.sync_dao_members(LINK_ID.into());
+        on_owners_page(&mut contract, success(&vec![(accounts(4), U128(1))]));
+        resolve_paged_owners(&mut contract);
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        let result = contract.handle_dao_members_sync(LINK_ID.into(), accounts(1), U128(0));
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        let progress = contract.get_sync_progress(LINK_ID.into()).unwrap();
+        assert!(!progress.fetching);
+
+        // The next call fetches DAO policy again
+        resolve_paged_owners(&mut contract);
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![],
+            sample_policy(&[]),
+        );
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert!(contract.get_sync_progress(LINK_ID.into()).is_none());
+    }
+
+    #[test]
+    fn test_reclaim_bonds() {
+        let mut contract = setup_contract();
+        testing_env!(get_context(accounts(1))
+            .attached_deposit(PROPOSAL_BOND * 3)
+            .build());
+        contract.deposit_bond();
+        handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4)]],
+            sample_policy_with_bond(&[accounts(5)], PROPOSAL_BOND),
+        );
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![success(&7)],
+        );
+        contract.on_proposals_added(
+            LINK_ID.into(),
+            U128(PROPOSAL_BOND * 2),
+            accounts(1),
+            U128(0),
+            vec![],
+        );
+
+        // One of the two bonds is back on the account, besides storage and the operating reserve
+        let storage_usage = 1_000;
+        let kept = storage_usage as Balance * env::storage_byte_cost() + DEFAULT_OPERATING_RESERVE;
+        let mut context = get_context(accounts(1));
+        context.storage_usage(storage_usage);
+        testing_env!(context.account_balance(kept + PROPOSAL_BOND * 2).build());
+        let bond_info = contract.reclaim_bonds();
+        assert_eq!(bond_info.balance.0, PROPOSAL_BOND * 2);
+        assert_eq!(bond_info.spent.0, PROPOSAL_BOND);
+
+        // A larger operating reserve is left out too
+        testing_env!(context.account_balance(kept + PROPOSAL_BOND * 10).build());
+        contract.set_operating_reserve(U128(DEFAULT_OPERATING_RESERVE + PROPOSAL_BOND * 7));
+        let bond_info = contract.reclaim_bonds();
+        assert_eq!(bond_info.balance.0, PROPOSAL_BOND * 3);
+        assert_eq!(bond_info.spent.0, 0);
+    }
+
+    #[test]
+    fn test_failed_proposals_return_bond() {
+        let mut contract = setup_contract();
+        testing_env!(get_context(accounts(1))
+            .attached_deposit(PROPOSAL_BOND * 3)
+            .build());
+        contract.deposit_bond();
+
+        handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4)]],
+            sample_policy_with_bond(&[accounts(5)], PROPOSAL_BOND),
+        );
+
+        testing_env!(
+            get_context(accounts(0)).build(),
+            VMConfig::test(),
+            RuntimeFeesConfig::test(),
+            Default::default(),
+            vec![PromiseResult::Failed],
+        );
+        let result = contract.on_proposals_added(
+            LINK_ID.into(),
+            U128(PROPOSAL_BOND * 2),
+            accounts(1),
+            U128(0),
+            vec![],
+        );
+        assert_eq!(result, None);
+
+        let bond_info = contract.get_bond_info();
+        assert_eq!(bond_info.balance.0, PROPOSAL_BOND * 3);
+        assert_eq!(bond_info.spent.0, 0);
+        assert!(get_logs()[0].contains("\"event\":\"proposals_failed\""));
+    }
+
+    #[test]
+    fn test_caller_bond_kept_when_members_match() {
+        let mut contract = setup_contract();
+        let caller_bond = SyncSettings::default().caller_bond.0;
+        testing_env!(get_context(accounts(5))
+            .block_timestamp(42)
+            .attached_deposit(caller_bond)
+            .build());
+        contract.sync_dao_members(LINK_ID.into());
+        assert!(contract.get_link(LINK_ID.into()).unwrap().syncing);
+        handle_sync(
+            &mut contract,
+            get_context(accounts(0)).block_timestamp(43).build(),
+            LINK_ID,
+            caller_bond,
+            vec![vec![accounts(4)]],
+            sample_policy(&[accounts(4)]),
+        );
+
+        assert_eq!(contract.get_bond_info().balance.0, caller_bond);
+        let link = contract.get_link(LINK_ID.into()).unwrap();
+        assert!(!link.syncing);
+        assert_eq!(link.last_sync_at, Some(43.into()));
+    }
+
+    #[test]
+    fn test_anyone_can_sync_after_interval() {
+        let mut contract = setup_contract();
+        testing_env!(get_context(accounts(1)).block_timestamp(42).build());
+        contract.sync_dao_members(LINK_ID.into());
+        handle_sync(
+            &mut contract,
+            get_context(accounts(0)).block_timestamp(42).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4)]],
+            sample_policy(&[]),
+        );
+        // The interval runs from when the DAO accepted the proposals
+        on_proposals_added(&mut contract, LINK_ID, success(&7), vec![]);
+        assert_eq!(
+            contract.get_link(LINK_ID.into()).unwrap().last_sync_at,
+            Some(42.into())
+        );
+
+        let settings = SyncSettings::default();
+        testing_env!(get_context(accounts(5))
+            .block_timestamp(42 + settings.min_interval.0)
+            .attached_deposit(settings.caller_bond.0)
+            .build());
+        contract.sync_dao_members(LINK_ID.into());
+    }
+
+    #[test]
+    fn test_links_are_synced_separately() {
+        let mut contract = setup_contract();
+        contract.add_link(
+            "passes".into(),
+            vec![accounts(4)],
+            accounts(5),
+            Some("pass holders".into()),
+        );
+        assert_eq!(contract.get_links_count().0, 2);
+
+        testing_env!(get_context(accounts(1)).block_timestamp(42).build());
+        contract.sync_dao_members("passes".into());
+        handle_sync(
+            &mut contract,
+            get_context(accounts(0)).block_timestamp(42).build(),
+            "passes",
+            0,
+            vec![vec![accounts(4)]],
+            sample_policy(&[]),
+        );
+        on_proposals_added(&mut contract, "passes", success(&7), vec![]);
+
+        let links = contract.get_links(None, None);
+        assert_eq!(links.len(), 2);
+        let goblins = links.iter().find(|view| view.link_id == LINK_ID).unwrap();
+        let passes = links.iter().find(|view| view.link_id == "passes").unwrap();
+        assert_eq!(goblins.link.last_sync_at, None);
+        assert_eq!(passes.link.last_sync_at, Some(42.into()));
+        assert_eq!(passes.link.dao_owners_role, "pass holders");
+    }
+
+    #[test]
+    fn test_membership_rules() {
+        let owners_per_collection = || {
+            vec![
+                token_counts(&[(accounts(1), 1), (accounts(2), 1), (accounts(3), 1)]),
+                token_counts(&[(accounts(2), 2), (accounts(3), 1)]),
+                token_counts(&[(accounts(3), 3), (accounts(4), 1)]),
+            ]
+        };
+
+        assert_eq!(
+            MembershipRule::Union.combine(owners_per_collection()),
+            token_counts(&[
+                (accounts(1), 1),
+                (accounts(2), 3),
+                (accounts(3), 5),
+                (accounts(4), 1)
+            ])
+        );
+        assert_eq!(
+            MembershipRule::Intersection.combine(owners_per_collection()),
+            token_counts(&[(accounts(3), 5)])
+        );
+        assert_eq!(
+            MembershipRule::AtLeast(2).combine(owners_per_collection()),
+            token_counts(&[(accounts(2), 3), (accounts(3), 5)])
+        );
+    }
+
+    #[test]
+    fn test_tier_modes() {
+        let mut contract = setup_contract();
+        let owners = token_counts(&[(accounts(2), 1), (accounts(3), 12), (accounts(4), 30)]);
+
+        contract.set_role_assignment(LINK_ID.into(), sample_tiers(TierMode::HighestOnly));
+        let link = contract.get_link(LINK_ID.into()).unwrap();
+        assert_eq!(link.owners_view(), OwnersView::OwnerCounts);
+        assert_eq!(
+            link.role_members(&owners),
+            vec![
+                ("council".to_string(), owner_set(&[accounts(4)])),
+                ("whales".to_string(), owner_set(&[accounts(3)])),
+                ("holders".to_string(), owner_set(&[accounts(2)])),
+            ]
+        );
+
+        contract.set_role_assignment(LINK_ID.into(), sample_tiers(TierMode::Every));
+        let link = contract.get_link(LINK_ID.into()).unwrap();
+        assert_eq!(
+            link.role_members(&owners),
+            vec![
+                ("council".to_string(), owner_set(&[accounts(4)])),
+                ("whales".to_string(), owner_set(&[accounts(3), accounts(4)])),
+                (
+                    "holders".to_string(),
+                    owner_set(&[accounts(2), accounts(3), accounts(4)])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sync_updates_tiers_in_one_proposal() {
+        let mut contract = setup_contract();
+        testing_env!(get_context(accounts(1))
+            .attached_deposit(PROPOSAL_BOND * 3)
+            .build());
+        contract.deposit_bond();
+        contract.set_role_assignment(LINK_ID.into(), sample_tiers(TierMode::HighestOnly));
+
+        let counts: HashMap<AccountId, U128> =
+            vec![(accounts(4), U128(1)), (accounts(5), U128(10))]
+                .into_iter()
+                .collect();
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![success(&counts)],
+            sample_policy_with_bond(&[accounts(4), accounts(5)], PROPOSAL_BOND),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        assert_eq!(contract.get_bond_info().spent.0, PROPOSAL_BOND);
+        let logs = get_logs();
+        assert_eq!(logs.len(), 3);
+        assert!(logs[0].contains("\"role\":\"council\",\"added\":0,\"removed\":0"));
+        assert!(logs[1].contains("\"role\":\"whales\",\"added\":1,\"removed\":0"));
+        assert!(logs[2].contains("\"role\":\"holders\",\"added\":0,\"removed\":1"));
+    }
+
+    #[test]
+    fn test_sync_combines_collections() {
+        let mut contract = setup_contract();
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_nft_contracts(
+            LINK_ID.into(),
+            vec![accounts(2), accounts(3)],
+            MembershipRule::Intersection,
+        );
+
+        let result = handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4), accounts(5)], vec![accounts(5)]],
+            sample_policy(&[accounts(5)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+    }
+
+    fn clan_roles() -> RoleAssignment {
+        RoleAssignment::Attribute {
+            attribute: "clan".into(),
+            roles: vec![
+                ("red".to_string(), "Red Clan".to_string()),
+                ("crimson".to_string(), "Red Clan".to_string()),
+                ("blue".to_string(), "Blue Clan".to_string()),
+            ]
+            .into_iter()
+            .collect(),
+        }
+    }
+
+    fn owners_by_value(owners: &[(&str, AccountId)]) -> HashMap<String, HashSet<AccountId>> {
+        let mut owners_by_value: HashMap<String, HashSet<AccountId>> = HashMap::new();
+        for (value, owner_id) in owners {
+            owners_by_value
+                .entry(value.to_string())
+                .or_default()
+                .insert(owner_id.clone());
+        }
+        owners_by_value
+    }
+
+    #[test]
+    fn test_attribute_role_members() {
+        let mut contract = setup_contract();
+        contract.set_role_assignment(LINK_ID.into(), clan_roles());
+        contract.set_nft_contracts(
+            LINK_ID.into(),
+            vec![accounts(2), accounts(3)],
+            MembershipRule::Intersection,
+        );
+
+        let link = contract.get_link(LINK_ID.into()).unwrap();
+        assert_eq!(
+            link.owners_view(),
+            OwnersView::OwnersByAttribute("clan".into())
+        );
+        let members = link.attribute_role_members(vec![
+            owners_by_value(&[
+                ("red", accounts(1)),
+                ("crimson", accounts(2)),
+                ("blue", accounts(3)),
+                ("green", accounts(4)),
+            ]),
+            owners_by_value(&[("crimson", accounts(1)), ("red", accounts(2))]),
+        ]);
+        assert_eq!(
+            members,
+            vec![
+                ("Blue Clan".to_string(), owner_set(&[])),
+                (
+                    "Red Clan".to_string(),
+                    owner_set(&[accounts(1), accounts(2)])
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_sync_updates_attribute_roles_in_one_proposal() {
+        let mut contract = setup_contract();
+        contract.set_role_assignment(LINK_ID.into(), clan_roles());
+
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![success(&owners_by_value(&[
+                ("red", accounts(4)),
+                ("blue", accounts(5)),
+            ]))],
+            sample_policy(&[accounts(4), accounts(5)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        let logs = get_logs();
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].contains("\"role\":\"Blue Clan\",\"added\":1"));
+        assert!(logs[1].contains("\"role\":\"Red Clan\",\"added\":1"));
+    }
+
+    #[test]
+    fn test_paged_attribute_sync() {
+        let mut contract = setup_contract();
+        contract.set_role_assignment(LINK_ID.into(), clan_roles());
+        contract.set_page_size(LINK_ID.into(), Some(U64(2)));
+        contract.sync_dao_members(LINK_ID.into());
+
+        let progress = on_owners_page(
+            &mut contract,
+            success(&json!([
+                ["1", accounts(4), "red"],
+                ["2", accounts(5), "blue"],
+            ])),
+        );
+        assert_eq!(progress.from_token_id, Some("2".to_string()));
+
+        // Values sharing a role count the owner once
+        testing_env!(get_context(accounts(0)).build());
+        contract.sync_dao_members(LINK_ID.into());
+        let progress = on_owners_page(
+            &mut contract,
+            success(&json!([
+                ["3", accounts(4), "crimson"],
+                ["4", accounts(3), null]
+            ])),
+        );
+        assert_eq!(progress.collection_index, U64(0));
+        testing_env!(get_context(accounts(0)).build());
+        contract.sync_dao_members(LINK_ID.into());
+        let progress = on_owners_page(&mut contract, success(&json!([])));
+        assert_eq!(progress.collection_index, U64(1));
+        assert_eq!(progress.owners_count, U64(2));
+        resolve_paged_owners(&mut contract);
+
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![],
+            sample_policy(&[]),
+        );
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        let logs = get_logs();
+        assert_eq!(logs.len(), 2);
+        assert!(logs[0].contains("\"role\":\"Blue Clan\",\"added\":1"));
+        assert!(logs[1].contains("\"role\":\"Red Clan\",\"added\":1"));
+    }
+
+    #[test]
+    fn test_sync_uses_delegates() {
+        let mut contract = setup_contract();
+        contract.set_use_delegates(LINK_ID.into(), true);
+        let link = contract.get_link(LINK_ID.into()).unwrap();
+        assert_eq!(link.owners_view(), OwnersView::Votes);
+
+        let votes: HashMap<AccountId, U128> = vec![(accounts(5), U128(3))].into_iter().collect();
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![success(&votes)],
+            sample_policy(&[accounts(5)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+    }
+
+    #[test]
+    fn test_sync_from_snapshot() {
+        let mut contract = setup_contract();
+        contract.set_snapshot(LINK_ID.into(), Some("season-2".into()));
+        contract.set_role_assignment(LINK_ID.into(), sample_tiers(TierMode::HighestOnly));
+        let link = contract.get_link(LINK_ID.into()).unwrap();
+        assert_eq!(
+            link.owners_view(),
+            OwnersView::SnapshotHolders("season-2".into())
+        );
+
+        let holders = vec![(accounts(4), U128(12)), (accounts(5), U128(1))];
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![success(&holders)],
+            sample_policy(&[accounts(5)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        let logs = get_logs();
+        assert!(logs[1].contains("\"role\":\"whales\",\"added\":1,\"removed\":0"));
+        assert!(logs[2].contains("\"role\":\"holders\",\"added\":0,\"removed\":0"));
+    }
+
+    #[test]
+    fn test_sync_applies_holding_settings() {
+        let mut contract = setup_contract();
+        contract.set_proposal_mode(LINK_ID.into(), ProposalMode::ChangePolicy);
+        contract.set_holding_settings(
+            LINK_ID.into(),
+            HoldingSettings {
+                min_holding_period: U64(100),
+                removal_grace_period: U64(50),
+            },
+        );
+
+        let owners = owner_set(&[accounts(4), accounts(2)]);
+        let holders_since: HashMap<AccountId, U64> =
+            vec![(accounts(4), U64(950)), (accounts(2), U64(800))]
+                .into_iter()
+                .collect();
+        let sync_at = |contract: &mut Contract, timestamp: u64| {
+            handle_sync_results(
+                contract,
+                get_context(accounts(0)).block_timestamp(timestamp).build(),
+                LINK_ID,
+                0,
+                vec![success(&owners), success(&holders_since)],
+                sample_policy(&[accounts(5)]),
+            );
+            get_logs()
+        };
+
+        // accounts(4) has not held long enough, accounts(5) is kept for the grace period
+        let logs = sync_at(&mut contract, 1000);
+        assert!(logs[0].contains("\"added\":1,\"removed\":0,\"unchanged\":1"));
+        assert_eq!(
+            contract.get_link(LINK_ID.into()).unwrap().pending_removals,
+            vec![PendingRemoval {
+                role: "holders".into(),
+                account_id: accounts(5),
+                since: U64(1000),
+            }]
+        );
+
+        let logs = sync_at(&mut contract, 1060);
+        assert!(logs[0].contains("\"added\":2,\"removed\":1,\"unchanged\":0"));
+    }
+
+    #[test]
+    fn test_sync_skips_excluded_accounts() {
+        let mut contract = setup_contract();
+        let marketplace: AccountId = "market.paras.near".parse().unwrap();
+        let escrow: AccountId = "escrow.near".parse().unwrap();
+        let exclusions = contract.add_exclusions(vec![escrow.clone()], vec![".paras.near".into()]);
+        assert!(exclusions.is_excluded(&marketplace));
+        assert!(!exclusions.is_excluded(&"paras.near".parse().unwrap()));
+
+        let result = handle_sync(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![vec![accounts(4), marketplace, escrow]],
+            sample_policy(&[accounts(4)]),
+        );
+
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+    }
+
+    #[test]
+    fn test_merge_modes() {
+        let mut contract = setup_contract();
+        contract.set_proposal_mode(LINK_ID.into(), ProposalMode::ChangePolicy);
+        let sync = |contract: &mut Contract| {
+            handle_sync(
+                contract,
+                get_context(accounts(0)).build(),
+                LINK_ID,
+                0,
+                vec![vec![accounts(4)]],
+                sample_policy(&[accounts(2), accounts(3)]),
+            );
+            let logs = get_logs();
+            on_proposals_added(contract, LINK_ID, success(&7), vec![]);
+            logs
+        };
+
+        contract.set_merge_mode(
+            LINK_ID.into(),
+            MergeMode::Pinned {
+                accounts: vec![accounts(2), accounts(5)],
+            },
+        );
+        // Pinned accounts are added even if they are not members yet
+        assert!(sync(&mut contract)[0].contains("\"added\":2,\"removed\":1,\"unchanged\":1"));
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.set_merge_mode(LINK_ID.into(), MergeMode::AddOnly);
+        assert!(sync(&mut contract)[0].contains("\"added\":1,\"removed\":0,\"unchanged\":2"));
+
+        let proposal = change_policy_proposal(
+            sample_policy(&[]),
+            &[("holders".into(), MembershipSummary::default())],
+            &MergeMode::AddOnly.description(),
+        );
+        assert_eq!(
+            proposal["description"],
+            "Update holders members: 0 added, 0 removed, 0 unchanged. \
+             NFT owners are added, no member is removed"
+        );
+    }
+
+    #[test]
+    fn test_paged_sync() {
+        let mut contract = setup_contract();
+        contract.set_page_size(LINK_ID.into(), Some(U64(2)));
+        contract.sync_dao_members(LINK_ID.into());
+        assert!(contract.get_sync_progress(LINK_ID.into()).unwrap().fetching);
+
+        // An invalid page is fetched again by the next call
+        let progress = on_owners_page(&mut contract, success(&"owners"));
+        assert!(!progress.fetching);
+        assert_eq!(progress.from_index, U64(0));
+        assert!(get_logs()[0].contains("\"event\":\"sync_failed\""));
+
+        testing_env!(get_context(accounts(1)).build());
+        contract.sync_dao_members(LINK_ID.into());
+        let progress = on_owners_page(
+            &mut contract,
+            success(&vec![(accounts(4), U128(1)), (accounts(5), U128(3))]),
+        );
+        assert_eq!(progress.collection_index, U64(0));
+        assert_eq!(progress.from_index, U64(2));
+        assert_eq!(progress.from_account_id, Some(accounts(5)));
+
+        // An owner returned twice by one collection is only counted once
+        testing_env!(get_context(accounts(1)).build());
+        contract.sync_dao_members(LINK_ID.into());
+        let progress = on_owners_page(&mut contract, success(&vec![(accounts(4), U128(1))]));
+        assert_eq!(progress.collection_index, U64(1));
+        assert_eq!(progress.owners_count, U64(2));
+
+        // Collected owners are resolved a page at a time, then DAO policy is fetched
+        testing_env!(get_context(accounts(0)).build());
+        match contract.sync_dao_members(LINK_ID.into()) {
+            PromiseOrValue::Value(progress) => assert_eq!(progress.owners_count, U64(0)),
+            PromiseOrValue::Promise(_) => panic!("Expected collected owners to be resolved"),
+        }
+        let result = contract.sync_dao_members(LINK_ID.into());
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![],
+            sample_policy(&[accounts(5), accounts(4)]),
+        );
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+        assert!(contract.get_sync_progress(LINK_ID.into()).is_none());
+    }
+
+    #[test]
+    fn test_paged_sync_applies_min_holding_period() {
+        let mut contract = setup_contract();
+        contract.set_proposal_mode(LINK_ID.into(), ProposalMode::ChangePolicy);
+        contract.set_holding_settings(
+            LINK_ID.into(),
+            HoldingSettings {
+                min_holding_period: U64(100),
+                removal_grace_period: U64(0),
+            },
+        );
+        contract.set_page_size(LINK_ID.into(), Some(U64(2)));
+        contract.sync_dao_members(LINK_ID.into());
+
+        let progress = on_owners_page(
+            &mut contract,
+            success(&vec![
+                (accounts(4), U128(1), Some(U64(950))),
+                (accounts(5), U128(2), Some(U64(800))),
+            ]),
+        );
+        assert_eq!(progress.from_account_id, Some(accounts(5)));
+        testing_env!(get_context(accounts(1)).build());
+        contract.sync_dao_members(LINK_ID.into());
+        // Owners without a known holding time haven't held long enough
+        on_owners_page(
+            &mut contract,
+            success(&vec![(accounts(2), U128(1), None::<U64>)]),
+        );
+        resolve_paged_owners(&mut contract);
+
+        // accounts(4) has not held long enough yet
+        handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).block_timestamp(1000).build(),
+            LINK_ID,
+            0,
+            vec![],
+            sample_policy(&[]),
+        );
+        assert!(get_logs()[0].contains("\"added\":1,\"removed\":0,\"unchanged\":0"));
+    }
+
+    #[test]
+    fn test_sync_falls_back_to_enumeration() {
+        let mut contract = setup_contract();
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![PromiseResult::Failed],
+            sample_policy(&[accounts(4)]),
+        );
+        assert!(matches!(result, PromiseOrValue::Promise(_)));
+        let progress = contract.get_sync_progress(LINK_ID.into()).unwrap();
+        assert!(!progress.use_enumeration);
+
+        // The collection doesn't implement `nft_owners_paged` either
+        let progress = on_owners_page(&mut contract, PromiseResult::Failed);
+        assert!(progress.use_enumeration);
+        assert_eq!(progress.from_index, U64(0));
+
+        testing_env!(get_context(accounts(0)).build());
+        contract.sync_dao_members(LINK_ID.into());
+        let tokens = json!([
+            { "token_id": "1", "owner_id": accounts(4), "metadata": null },
+            { "token_id": "2", "owner_id": accounts(5), "metadata": null },
+            { "token_id": "3", "owner_id": accounts(4), "metadata": null },
+        ]);
+        let progress = on_owners_page(&mut contract, success(&tokens));
+        assert_eq!(progress.collection_index, U64(1));
+        assert!(!progress.use_enumeration);
+        assert_eq!(progress.owners_count, U64(2));
+        resolve_paged_owners(&mut contract);
+
+        let result = handle_sync_results(
+            &mut contract,
+            get_context(accounts(0)).build(),
+            LINK_ID,
+            0,
+            vec![],
+            sample_policy(&[accounts(5), accounts(4)]),
+        );
+        assert!(matches!(result, PromiseOrValue::Value(None)));
+    }
+
+    #[test]
+    fn test_cancel_sync_in_chunks() {
+        let mut contract = setup_contract();
+        contract.set_page_size(LINK_ID.into(), Some(U64(2)));
+        testing_env!(get_context(accounts(2))
+            .attached_deposit(PROPOSAL_BOND)
+            .build());
+        contract.sync_dao_members(LINK_ID.into());
+        on_owners_page(
+            &mut contract,
+            success(&vec![(accounts(3), U128(1)), (accounts(4), U128(1))]),
+        );
+        testing_env!(get_context(accounts(0)).build());
+        contract.sync_dao_members(LINK_ID.into());
+        on_owners_page(&mut contract, success(&vec![(accounts(5), U128(1))]));
+
+        testing_env!(get_context(accounts(1)).build());
+        assert!(!contract.cancel_sync(LINK_ID.into()));
+        let progress = contract.get_sync_progress(LINK_ID.into()).unwrap();
+        assert!(progress.cancelled);
+        assert_eq!(progress.owners_count, U64(1));
+
+        assert!(contract.cancel_sync(LINK_ID.into()));
+        assert!(contract.get_sync_progress(LINK_ID.into()).is_none());
+    }
+
+    #[test]
+    fn test_migrate() {
+        testing_env!(get_context(accounts(0)).build());
+        env::state_write(&migrate::OldContract {
+            owner_id: accounts(1),
+            nft_contract_id: accounts(2),
+            dao_contract_id: accounts(3),
+            dao_owners_role: String::new(),
+        });
+
+        let contract = Contract::migrate();
+        assert_eq!(contract.owner_id, accounts(1));
+        let link = contract.get_link(migrate::DEFAULT_LINK_ID.into()).unwrap();
+        assert_eq!(link.nft_contract_ids, vec![accounts(2)]);
+        assert_eq!(link.dao_contract_id, accounts(3));
+        assert_eq!(link.dao_owners_role, "holders");
+        assert!(link.last_sync_at.is_none());
+    }
+}
@@ -0,0 +1,772 @@
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::json;
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BTreeSet};
+
+use crate::*;
+
+/// Name of the DAO role for NFT owners unless another one is given in `add_link` or `set_dao_role`.
+const DEFAULT_DAO_ROLE: &str = "holders";
+/// One day in nanoseconds.
+const DEFAULT_SYNC_MIN_INTERVAL: u64 = 24 * 60 * 60 * 1_000_000_000;
+/// 0.1 NEAR.
+const DEFAULT_SYNC_CALLER_BOND: Balance = 100_000_000_000_000_000_000_000;
+
+pub type LinkId = String;
+
+/// Defines which proposals are submitted to the DAO on sync.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum ProposalMode {
+    /// A single `ChangePolicy` proposal that replaces the whole policy.
+    ChangePolicy,
+    /// One `AddMemberToRole` / `RemoveMemberFromRole` proposal per changed account. A change isn't
+    /// proposed again while its proposal can still be voted on.
+    /// Links filling several roles always use `ChangePolicy` to update them together.
+    MemberChanges,
+}
+
+/// Defines how owners of several NFT collections are combined into DAO members.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum MembershipRule {
+    /// Owners of a token of any collection.
+    Union,
+    /// Owners of a token of every collection.
+    Intersection,
+    /// Owners of tokens of at least given number of collections.
+    AtLeast(u32),
+}
+
+impl MembershipRule {
+    /// Number of collections out of `collections` an owner needs tokens of to become a DAO member.
+    pub fn min_collections(&self, collections: usize) -> usize {
+        match self {
+            MembershipRule::Union => 1,
+            MembershipRule::Intersection => collections,
+            MembershipRule::AtLeast(count) => *count as usize,
+        }
+    }
+
+    /// Combine owners of every collection into DAO members with the total number of tokens they hold.
+    pub fn combine(
+        &self,
+        owners_per_collection: Vec<HashMap<AccountId, u64>>,
+    ) -> HashMap<AccountId, u64> {
+        let min_collections = self.min_collections(owners_per_collection.len());
+
+        let mut collections_per_owner: HashMap<AccountId, (usize, u64)> = HashMap::new();
+        for owners in owners_per_collection {
+            for (owner_id, tokens) in owners {
+                let entry = collections_per_owner.entry(owner_id).or_default();
+                entry.0 += 1;
+                entry.1 += tokens;
+            }
+        }
+
+        collections_per_owner
+            .into_iter()
+            .filter(|(_, (collections, _))| *collections >= min_collections)
+            .map(|(owner_id, (_, tokens))| (owner_id, tokens))
+            .collect()
+    }
+}
+
+/// Role given to owners of at least `min_tokens` tokens.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Tier {
+    pub role: String,
+    pub min_tokens: u64,
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum TierMode {
+    /// An owner is only a member of the highest tier they qualify for.
+    HighestOnly,
+    /// An owner is a member of every tier they qualify for.
+    Every,
+}
+
+/// Defines which DAO roles NFT owners are put in.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum RoleAssignment {
+    /// Every owner is a member of `dao_owners_role`.
+    Holders,
+    /// Owners are put in roles by the number of tokens they hold.
+    Tiers { tiers: Vec<Tier>, mode: TierMode },
+    /// Owners are put in roles by the value of `attribute` in the JSON `extra` metadata of their tokens.
+    /// `roles` maps attribute values to DAO roles, several values can share a role.
+    Attribute {
+        attribute: String,
+        roles: BTreeMap<String, String>,
+    },
+}
+
+/// Restrictions for accounts other than the owner calling `sync_dao_members`.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SyncSettings {
+    /// Minimum time in nanoseconds between two syncs.
+    pub min_interval: U64,
+    /// Deposit the caller has to attach. It is refunded if the sync submits proposals, whether the
+    /// DAO accepts them or not, or fails, and added to the bond balance if members are already in sync.
+    pub caller_bond: U128,
+}
+
+impl Default for SyncSettings {
+    fn default() -> Self {
+        Self {
+            min_interval: U64(DEFAULT_SYNC_MIN_INTERVAL),
+            caller_bond: U128(DEFAULT_SYNC_CALLER_BOND),
+        }
+    }
+}
+
+/// Defines what happens to DAO members that are not NFT owners.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub enum MergeMode {
+    /// Members are replaced with NFT owners.
+    Replace,
+    /// Members are replaced with NFT owners and `accounts`, which are always members.
+    Pinned { accounts: Vec<AccountId> },
+    /// NFT owners are added, no member is ever removed.
+    AddOnly,
+}
+
+impl MergeMode {
+    /// Add members the mode keeps besides NFT owners.
+    pub fn apply(
+        &self,
+        members_per_role: &mut [(String, HashSet<AccountId>)],
+        policy: &Policy,
+    ) -> Result<(), String> {
+        for (role, members) in members_per_role.iter_mut() {
+            match self {
+                MergeMode::Replace => {}
+                MergeMode::Pinned { accounts } => members.extend(accounts.iter().cloned()),
+                MergeMode::AddOnly => members.extend(policy.dao_members(role)?.iter().cloned()),
+            }
+        }
+        Ok(())
+    }
+
+    /// Explanation for DAO members voting on proposals.
+    pub fn description(&self) -> String {
+        match self {
+            MergeMode::Replace => "Members are replaced with NFT owners".to_string(),
+            MergeMode::Pinned { accounts } => format!(
+                "Members are replaced with NFT owners, pinned accounts are always members: {}",
+                accounts
+                    .iter()
+                    .map(AccountId::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            MergeMode::AddOnly => "NFT owners are added, no member is removed".to_string(),
+        }
+    }
+}
+
+/// Protection against accounts that buy a token right before a sync and sell it right after.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct HoldingSettings {
+    /// Time in nanoseconds an owner has to hold tokens continuously before it is added to the DAO.
+    pub min_holding_period: U64,
+    /// Time in nanoseconds a member that stopped holding tokens stays in the DAO before it is removed.
+    pub removal_grace_period: U64,
+}
+
+impl Default for HoldingSettings {
+    fn default() -> Self {
+        Self {
+            min_holding_period: U64(0),
+            removal_grace_period: U64(0),
+        }
+    }
+}
+
+/// DAO member that is not an owner anymore and waits for `removal_grace_period` to pass.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct PendingRemoval {
+    pub role: String,
+    pub account_id: AccountId,
+    /// Block timestamp of the first sync that found the member without tokens.
+    pub since: U64,
+}
+
+/// Membership change submitted to the DAO as a separate proposal.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Debug, Deserialize, PartialEq, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct ProposedChange {
+    pub role: String,
+    pub account_id: AccountId,
+    pub membership_type: MembershipType,
+    /// Block timestamp when the proposal was submitted.
+    pub proposed_at: U64,
+}
+
+/// NFT collections whose owners are synchronized with a role of a DAO.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct Link {
+    pub nft_contract_ids: Vec<AccountId>,
+    /// How owners of `nft_contract_ids` are combined into DAO members.
+    pub membership_rule: MembershipRule,
+    pub dao_contract_id: AccountId,
+    pub dao_owners_role: String,
+    /// Which DAO roles owners are put in.
+    pub role_assignment: RoleAssignment,
+    /// Use accounts holding delegated voting power (`nft_votes`) instead of raw owners.
+    pub use_delegates: bool,
+    /// Name of the NFT snapshot to take owners from instead of live owners.
+    pub snapshot: Option<String>,
+    /// Fetch owners in pages of this size, one page per `sync_dao_members` call, instead of all at once.
+    pub page_size: Option<U64>,
+    pub proposal_mode: ProposalMode,
+    /// Permissions and vote policy of roles created when they are missing in DAO policy.
+    pub role_template: RoleTemplate,
+    pub sync_settings: SyncSettings,
+    pub holding_settings: HoldingSettings,
+    pub merge_mode: MergeMode,
+    pub pending_removals: Vec<PendingRemoval>,
+    /// Changes whose proposals may still be open, they are skipped by the following syncs.
+    pub proposed_changes: Vec<ProposedChange>,
+    /// Block timestamp when the last successful sync finished: members were already in sync or
+    /// the DAO accepted its proposals.
+    pub last_sync_at: Option<U64>,
+    /// A sync has started and not finished: owners or DAO policy are being fetched, or submitted
+    /// proposals are waiting for the DAO. No other sync can start and the link can't be changed.
+    pub syncing: bool,
+}
+
+impl Link {
+    /// Link with default settings, `dao_owners_role` is `holders` unless given.
+    pub fn new(
+        nft_contract_ids: Vec<AccountId>,
+        dao_contract_id: AccountId,
+        dao_owners_role: Option<String>,
+    ) -> Self {
+        Self {
+            nft_contract_ids,
+            membership_rule: MembershipRule::Union,
+            dao_contract_id,
+            dao_owners_role: dao_owners_role.unwrap_or_else(|| DEFAULT_DAO_ROLE.to_string()),
+            role_assignment: RoleAssignment::Holders,
+            use_delegates: false,
+            snapshot: None,
+            page_size: None,
+            proposal_mode: ProposalMode::MemberChanges,
+            role_template: RoleTemplate::default(),
+            sync_settings: SyncSettings::default(),
+            holding_settings: HoldingSettings::default(),
+            merge_mode: MergeMode::Replace,
+            pending_removals: vec![],
+            proposed_changes: vec![],
+            last_sync_at: None,
+            syncing: false,
+        }
+    }
+
+    /// NFT contract view returning owners in the format the link needs.
+    pub fn owners_view(&self) -> OwnersView {
+        if let RoleAssignment::Attribute { attribute, .. } = &self.role_assignment {
+            return OwnersView::OwnersByAttribute(attribute.clone());
+        }
+        if let Some(snapshot) = &self.snapshot {
+            return OwnersView::SnapshotHolders(snapshot.clone());
+        }
+        match self.role_assignment {
+            _ if self.use_delegates => OwnersView::Votes,
+            RoleAssignment::Tiers { .. } => OwnersView::OwnerCounts,
+            _ => OwnersView::Owners,
+        }
+    }
+
+    /// Whether owners have to be fetched with the time since which they hold tokens.
+    pub fn needs_holders_since(&self) -> bool {
+        self.holding_settings.min_holding_period.0 > 0
+    }
+
+    /// View a sync in pages fetches owners from. Live owners come with the time since which they
+    /// hold tokens when a minimum holding period is set, which attribute roles can't be paged with.
+    pub fn paged_owners_method(&self) -> Option<&'static str> {
+        match self.owners_view() {
+            OwnersView::Owners | OwnersView::OwnerCounts if self.needs_holders_since() => {
+                Some("nft_holders_since_paged")
+            }
+            _ if self.needs_holders_since() => None,
+            owners_view => owners_view.paged_method_name(),
+        }
+    }
+
+    /// Delay membership changes by `holding_settings`: owners are only added once they have held
+    /// tokens for `min_holding_period`, members that are not owners anymore are only removed once
+    /// `removal_grace_period` has passed since a sync first found them without tokens.
+    pub fn apply_holding_settings(
+        &mut self,
+        members_per_role: &mut [(String, HashSet<AccountId>)],
+        policy: &Policy,
+        holders_since: Option<&HashMap<AccountId, u64>>,
+    ) -> Result<(), String> {
+        let now = env::block_timestamp();
+        let min_holding_period = self.holding_settings.min_holding_period.0;
+        let removal_grace_period = self.holding_settings.removal_grace_period.0;
+        let mut pending_removals = vec![];
+
+        for (role, members) in members_per_role.iter_mut() {
+            let current_members = policy.dao_members(role)?;
+            if let Some(holders_since) = holders_since {
+                members.retain(|account_id| {
+                    current_members.contains(account_id)
+                        || holders_since
+                            .get(account_id)
+                            .is_some_and(|since| now >= since + min_holding_period)
+                });
+            }
+            if removal_grace_period == 0 {
+                continue;
+            }
+
+            for account_id in current_members {
+                if members.contains(account_id) {
+                    continue;
+                }
+                let since = self
+                    .pending_removals
+                    .iter()
+                    .find(|removal| removal.role == *role && removal.account_id == *account_id)
+                    .map_or(now, |removal| removal.since.0);
+                if now < since + removal_grace_period {
+                    members.insert(account_id.clone());
+                }
+                // Kept until the member is actually removed, so a rejected removal is proposed
+                // again on the next sync.
+                pending_removals.push(PendingRemoval {
+                    role: role.clone(),
+                    account_id: account_id.clone(),
+                    since: U64(since),
+                });
+            }
+        }
+        self.pending_removals = pending_removals;
+        Ok(())
+    }
+
+    /// Members of every DAO role mapped from attribute values, owners of several collections being
+    /// combined by `membership_rule` role by role.
+    pub fn attribute_role_members(
+        &self,
+        owners_per_collection: Vec<HashMap<String, HashSet<AccountId>>>,
+    ) -> Vec<(String, HashSet<AccountId>)> {
+        let roles = self.attribute_roles();
+        let role_names: BTreeSet<&String> = roles.values().collect();
+
+        role_names
+            .into_iter()
+            .map(|role| {
+                let role_owners_per_collection = owners_per_collection
+                    .iter()
+                    .map(|owners_per_value| {
+                        roles
+                            .iter()
+                            .filter(|(_, value_role)| *value_role == role)
+                            .filter_map(|(value, _)| owners_per_value.get(value))
+                            .flatten()
+                            .map(|owner_id| (owner_id.clone(), 1))
+                            .collect()
+                    })
+                    .collect();
+                let members = self
+                    .membership_rule
+                    .combine(role_owners_per_collection)
+                    .into_keys()
+                    .collect();
+                (role.clone(), members)
+            })
+            .collect()
+    }
+
+    /// Members of every DAO role mapped from attribute values, from `members` already combined
+    /// role by role by a sync in pages.
+    pub fn paged_attribute_role_members(
+        &self,
+        members: Vec<PagedMember>,
+    ) -> Vec<(String, HashSet<AccountId>)> {
+        let mut members_per_role: BTreeMap<String, HashSet<AccountId>> = self
+            .attribute_roles()
+            .values()
+            .map(|role| (role.clone(), HashSet::new()))
+            .collect();
+        for (role, member_id, _, _) in members {
+            if let Some(role_members) = role.and_then(|role| members_per_role.get_mut(&role)) {
+                role_members.insert(member_id);
+            }
+        }
+        members_per_role.into_iter().collect()
+    }
+
+    fn attribute_roles(&self) -> &BTreeMap<String, String> {
+        match &self.role_assignment {
+            RoleAssignment::Attribute { roles, .. } => roles,
+            _ => env::panic_str("Link doesn't assign roles by attribute"),
+        }
+    }
+
+    /// Changes of `role` that weren't proposed during the last `proposal_period`, so proposals that
+    /// can still be voted on aren't submitted again. Forgets proposals that expired or whose change
+    /// isn't needed anymore.
+    pub fn unproposed_changes(
+        &mut self,
+        role: &str,
+        changes: Vec<(AccountId, MembershipType)>,
+        proposal_period: u64,
+    ) -> Vec<(AccountId, MembershipType)> {
+        let now = env::block_timestamp();
+        self.proposed_changes.retain(|proposed| {
+            proposed.role == role
+                && now < proposed.proposed_at.0 + proposal_period
+                && changes.iter().any(|(account_id, membership_type)| {
+                    proposed.account_id == *account_id
+                        && proposed.membership_type == *membership_type
+                })
+        });
+        changes
+            .into_iter()
+            .filter(|(account_id, membership_type)| {
+                !self.proposed_changes.iter().any(|proposed| {
+                    proposed.account_id == *account_id
+                        && proposed.membership_type == *membership_type
+                })
+            })
+            .collect()
+    }
+
+    /// Members of every DAO role filled by the link.
+    pub fn role_members(
+        &self,
+        owners: &HashMap<AccountId, u64>,
+    ) -> Vec<(String, HashSet<AccountId>)> {
+        match &self.role_assignment {
+            RoleAssignment::Holders => {
+                vec![(
+                    self.dao_owners_role.clone(),
+                    owners.keys().cloned().collect(),
+                )]
+            }
+            RoleAssignment::Tiers { tiers, mode } => {
+                let mut tiers = tiers.clone();
+                tiers.sort_by_key(|tier| Reverse(tier.min_tokens));
+                let mut members: Vec<(String, HashSet<AccountId>)> = tiers
+                    .iter()
+                    .map(|tier| (tier.role.clone(), HashSet::new()))
+                    .collect();
+                for (owner_id, tokens) in owners {
+                    let qualified = tiers
+                        .iter()
+                        .zip(members.iter_mut())
+                        .filter(|(tier, _)| *tokens >= tier.min_tokens);
+                    for (_, (_, role_members)) in qualified {
+                        role_members.insert(owner_id.clone());
+                        if *mode == TierMode::HighestOnly {
+                            break;
+                        }
+                    }
+                }
+                members
+            }
+            RoleAssignment::Attribute { .. } => {
+                env::panic_str("Attribute roles are not based on token counts")
+            }
+        }
+    }
+}
+
+/// NFT contract view owners of a link are fetched with.
+#[derive(Debug, PartialEq)]
+pub enum OwnersView {
+    /// `nft_owners`: set of owners.
+    Owners,
+    /// `nft_owner_counts`: owners with the number of tokens they hold.
+    OwnerCounts,
+    /// `nft_votes`: accounts with their voting power.
+    Votes,
+    /// `nft_owners_by_attribute`: owners grouped by the value of a metadata attribute.
+    OwnersByAttribute(String),
+    /// `snapshot_holders`: owners with their token counts recorded in a snapshot.
+    SnapshotHolders(String),
+}
+
+impl OwnersView {
+    pub fn method_name(&self) -> &'static str {
+        match self {
+            OwnersView::Owners => "nft_owners",
+            OwnersView::OwnerCounts => "nft_owner_counts",
+            OwnersView::Votes => "nft_votes",
+            OwnersView::OwnersByAttribute(_) => "nft_owners_by_attribute",
+            OwnersView::SnapshotHolders(_) => "snapshot_holders",
+        }
+    }
+
+    /// View returning owners page by page with their token counts, if the source supports it.
+    pub fn paged_method_name(&self) -> Option<&'static str> {
+        match self {
+            OwnersView::Owners | OwnersView::OwnerCounts => Some("nft_owners_paged"),
+            OwnersView::SnapshotHolders(_) => Some("snapshot_holders"),
+            OwnersView::OwnersByAttribute(_) => Some("nft_owners_by_attribute_paged"),
+            OwnersView::Votes => None,
+        }
+    }
+
+    /// Whether owners can be counted from NEP-181 `nft_tokens` instead, for collections that
+    /// don't implement the owners views.
+    pub fn supports_enumeration(&self) -> bool {
+        matches!(self, OwnersView::Owners | OwnersView::OwnerCounts)
+    }
+
+    /// Arguments of the paged view. Live owners are paged by the last account of the previous page,
+    /// so owners leaving between pages don't shift the following ones; tokens by the last token
+    /// of the previous page; immutable snapshots by index.
+    pub fn paged_args(
+        &self,
+        from_index: u64,
+        from_account_id: Option<&AccountId>,
+        from_token_id: Option<&str>,
+        limit: u64,
+    ) -> Vec<u8> {
+        let args = match self {
+            OwnersView::SnapshotHolders(name) => json!({
+                "name": name,
+                "from_index": U64(from_index),
+                "limit": U64(limit),
+            }),
+            OwnersView::OwnersByAttribute(attribute) => json!({
+                "attribute": attribute,
+                "from_token_id": from_token_id,
+                "limit": U64(limit),
+            }),
+            _ => json!({
+                "from_account_id": from_account_id,
+                "limit": U64(limit),
+            }),
+        };
+        args.to_string().into_bytes()
+    }
+
+    pub fn args(&self) -> Vec<u8> {
+        match self {
+            OwnersView::OwnersByAttribute(attribute) => {
+                json!({ "attribute": attribute }).to_string().into_bytes()
+            }
+            OwnersView::SnapshotHolders(name) => json!({ "name": name }).to_string().into_bytes(),
+            _ => vec![],
+        }
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct LinkView {
+    pub link_id: LinkId,
+    #[serde(flatten)]
+    pub link: Link,
+}
+
+#[near_bindgen]
+impl Contract {
+    pub fn add_link(
+        &mut self,
+        link_id: LinkId,
+        nft_contract_ids: Vec<AccountId>,
+        dao_contract_id: AccountId,
+        dao_owners_role: Option<String>,
+    ) -> Link {
+        self.assert_owner();
+        require!(self.links.get(&link_id).is_none(), "Link already exists");
+        require!(
+            !nft_contract_ids.is_empty(),
+            "Requires at least one NFT contract"
+        );
+
+        let link = Link::new(nft_contract_ids, dao_contract_id, dao_owners_role);
+        self.links.insert(&link_id, &link);
+        link
+    }
+
+    pub fn remove_link(&mut self, link_id: LinkId) {
+        self.assert_owner();
+        require!(
+            !self.expect_link(&link_id).syncing,
+            "Link can't be changed while a sync is in progress"
+        );
+        self.links.remove(&link_id);
+    }
+
+    /// Set NFT collections of the link and the rule that combines their owners.
+    pub fn set_nft_contracts(
+        &mut self,
+        link_id: LinkId,
+        nft_contract_ids: Vec<AccountId>,
+        membership_rule: MembershipRule,
+    ) {
+        require!(
+            !nft_contract_ids.is_empty(),
+            "Requires at least one NFT contract"
+        );
+        if let MembershipRule::AtLeast(count) = membership_rule {
+            require!(
+                count > 0 && count as usize <= nft_contract_ids.len(),
+                "Invalid number of collections"
+            );
+        }
+        self.update_link(&link_id, |link| {
+            link.nft_contract_ids = nft_contract_ids;
+            link.membership_rule = membership_rule;
+        });
+    }
+
+    pub fn set_dao_role(&mut self, link_id: LinkId, role: String) {
+        self.update_link(&link_id, |link| link.dao_owners_role = role);
+    }
+
+    /// Set which DAO roles owners are put in.
+    pub fn set_role_assignment(&mut self, link_id: LinkId, role_assignment: RoleAssignment) {
+        if let RoleAssignment::Tiers { tiers, .. } = &role_assignment {
+            require!(!tiers.is_empty(), "Requires at least one tier");
+            require!(
+                tiers.iter().all(|tier| tier.min_tokens > 0),
+                "Tier requires at least one token"
+            );
+            let roles: HashSet<&String> = tiers.iter().map(|tier| &tier.role).collect();
+            require!(roles.len() == tiers.len(), "Tier roles must be unique");
+        }
+        if let RoleAssignment::Attribute { roles, .. } = &role_assignment {
+            require!(!roles.is_empty(), "Requires at least one attribute value");
+        }
+        self.update_link(&link_id, |link| {
+            link.role_assignment = role_assignment;
+            assert_owners_source_supported(link);
+        });
+    }
+
+    /// Sync accounts holding delegated voting power instead of raw owners.
+    /// Tiers then count votes instead of tokens.
+    pub fn set_use_delegates(&mut self, link_id: LinkId, use_delegates: bool) {
+        self.update_link(&link_id, |link| {
+            link.use_delegates = use_delegates;
+            assert_owners_source_supported(link);
+        });
+    }
+
+    /// Fetch owners of large collections in pages across several `sync_dao_members` calls.
+    pub fn set_page_size(&mut self, link_id: LinkId, page_size: Option<U64>) {
+        if let Some(page_size) = page_size {
+            require!(page_size.0 > 0, "Page size must be positive");
+        }
+        self.update_link(&link_id, |link| {
+            link.page_size = page_size;
+            assert_owners_source_supported(link);
+        });
+    }
+
+    pub fn set_holding_settings(&mut self, link_id: LinkId, settings: HoldingSettings) {
+        self.update_link(&link_id, |link| {
+            link.holding_settings = settings;
+            assert_owners_source_supported(link);
+        });
+    }
+
+    pub fn set_merge_mode(&mut self, link_id: LinkId, merge_mode: MergeMode) {
+        self.update_link(&link_id, |link| link.merge_mode = merge_mode);
+    }
+
+    /// Sync owners recorded in the NFT snapshot `snapshot` instead of live owners.
+    /// Every NFT contract of the link must have a snapshot with this name.
+    pub fn set_snapshot(&mut self, link_id: LinkId, snapshot: Option<String>) {
+        self.update_link(&link_id, |link| {
+            link.snapshot = snapshot;
+            assert_owners_source_supported(link);
+        });
+    }
+
+    pub fn set_proposal_mode(&mut self, link_id: LinkId, mode: ProposalMode) {
+        self.update_link(&link_id, |link| link.proposal_mode = mode);
+    }
+
+    /// Set permissions and vote policy of roles created when they are missing in DAO policy.
+    pub fn set_role_template(&mut self, link_id: LinkId, template: RoleTemplate) {
+        self.update_link(&link_id, |link| link.role_template = template);
+    }
+
+    pub fn set_sync_settings(&mut self, link_id: LinkId, settings: SyncSettings) {
+        self.update_link(&link_id, |link| link.sync_settings = settings);
+    }
+
+    pub fn get_link(&self, link_id: LinkId) -> Option<Link> {
+        self.links.get(&link_id)
+    }
+
+    pub fn get_links(&self, from_index: Option<U64>, limit: Option<U64>) -> Vec<LinkView> {
+        let from_index = from_index.map(|index| index.0).unwrap_or(0);
+        let limit = limit.map(|limit| limit.0).unwrap_or(u64::MAX);
+
+        self.links
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|(link_id, link)| LinkView { link_id, link })
+            .collect()
+    }
+
+    pub fn get_links_count(&self) -> U64 {
+        U64(self.links.len())
+    }
+}
+
+impl Contract {
+    pub(crate) fn expect_link(&self, link_id: &LinkId) -> Link {
+        self.links
+            .get(link_id)
+            .unwrap_or_else(|| env::panic_str("Link not found"))
+    }
+
+    fn update_link(&mut self, link_id: &LinkId, update: impl FnOnce(&mut Link)) {
+        self.assert_owner();
+        let mut link = self.expect_link(link_id);
+        require!(
+            !link.syncing,
+            "Link can't be changed while a sync is in progress"
+        );
+        update(&mut link);
+        self.links.insert(link_id, &link);
+    }
+}
+
+fn assert_owners_source_supported(link: &Link) {
+    if let RoleAssignment::Attribute { .. } = link.role_assignment {
+        require!(
+            !link.use_delegates && link.snapshot.is_none(),
+            "Attribute roles can only be assigned to live owners"
+        );
+    }
+    require!(
+        !link.use_delegates || link.snapshot.is_none(),
+        "Snapshots don't record delegates"
+    );
+    require!(
+        !link.needs_holders_since() || (!link.use_delegates && link.snapshot.is_none()),
+        "Minimum holding period only applies to live owners"
+    );
+    if link.page_size.is_some() {
+        require!(
+            link.paged_owners_method().is_some(),
+            "Owners of the link can't be fetched in pages"
+        );
+    }
+}
@@ -0,0 +1,43 @@
+use crate::*;
+
+/// Id of the link `migrate` creates from the state of a single-link contract.
+pub const DEFAULT_LINK_ID: &str = "default";
+
+/// State of contracts deployed before links were added, syncing a single pair of contracts.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldContract {
+    pub owner_id: AccountId,
+    pub nft_contract_id: AccountId,
+    pub dao_contract_id: AccountId,
+    pub dao_owners_role: String,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Convert the state of a single-link contract into a registry holding its contracts
+    /// and DAO role as the link `default`. A role that was never set becomes `holders`.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let old: OldContract =
+            env::state_read().unwrap_or_else(|| env::panic_str("Old state doesn't exist"));
+        let mut contract = Self {
+            owner_id: old.owner_id,
+            links: UnorderedMap::new(StorageKey::Links),
+            bond_balance: 0,
+            bond_spent: 0,
+            held_bonds: 0,
+            operating_reserve: DEFAULT_OPERATING_RESERVE,
+            exclusions: Exclusions::default(),
+            sync_progress: LookupMap::new(StorageKey::SyncProgress),
+        };
+        let dao_owners_role = Some(old.dao_owners_role).filter(|role| !role.is_empty());
+        let link = Link::new(
+            vec![old.nft_contract_id],
+            old.dao_contract_id,
+            dao_owners_role,
+        );
+        contract.links.insert(&DEFAULT_LINK_ID.to_string(), &link);
+        contract
+    }
+}
@@ -0,0 +1,441 @@
+use near_sdk::collections::{UnorderedMap, Vector};
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+
+use crate::*;
+
+/// Page size of syncs that fall back to `nft_tokens` for links without `page_size`.
+pub const DEFAULT_PAGE_SIZE: u64 = 100;
+
+/// Owners collected so far by a sync in pages.
+///
+/// Once every collection is fetched, each call resolves `page_size` collected owners into
+/// members, so the call submitting proposals doesn't go through every owner's tally.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct SyncProgress {
+    pub caller_id: AccountId,
+    pub caller_bond: Balance,
+    /// Index of the collection being fetched, equal to the number of collections once all are fetched.
+    pub collection_index: u64,
+    /// Index of the next owner, or token, to fetch from the current collection.
+    pub from_index: u64,
+    /// Last owner fetched from the current collection, the next page of `nft_owners_paged`
+    /// follows it.
+    pub from_account_id: Option<AccountId>,
+    /// Last token fetched from the current collection, the next page of
+    /// `nft_owners_by_attribute_paged` follows it.
+    pub from_token_id: Option<String>,
+    pub page_size: u64,
+    /// The current collection doesn't implement the owners views, its owners are counted
+    /// from NEP-181 `nft_tokens`.
+    pub use_enumeration: bool,
+    /// A page, or DAO policy after the last one, is being fetched. No other call of the sync can
+    /// start until it arrives.
+    pub fetching: bool,
+    /// Number of fetches started, the callback of the latest one is given it as `page`.
+    pub requested_pages: u64,
+    /// The owner is removing the sync with `cancel_sync`.
+    pub cancelled: bool,
+    /// Owners collected and not resolved yet. Links assigning roles by attribute tally owners
+    /// role by role, others without a role.
+    pub owners: UnorderedMap<(Option<String>, AccountId), OwnerTally>,
+    /// Owners that satisfy the membership rule with their roles and token counts, a chunk per call.
+    pub members: Vector<Vec<PagedMember>>,
+}
+
+/// DAO role, for links assigning roles by attribute, owner, its tokens and, for links with
+/// a minimum holding period, the time since which it holds them.
+pub type PagedMember = (Option<String>, AccountId, u64, Option<u64>);
+
+/// Tokens of an owner summed over the collections fetched so far.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OwnerTally {
+    pub collections: u64,
+    pub tokens: u64,
+    /// Index of the last collection the owner was counted in. Owners that show up twice in
+    /// one collection, e.g. in `nft_tokens` pages shifted by new tokens, are only counted once.
+    pub last_collection_index: u64,
+    /// Earliest time since which the owner holds tokens of a collection, for links with
+    /// a minimum holding period.
+    pub since: Option<u64>,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SyncProgressView {
+    pub caller_id: AccountId,
+    pub collection_index: U64,
+    pub from_index: U64,
+    pub from_account_id: Option<AccountId>,
+    pub from_token_id: Option<String>,
+    pub use_enumeration: bool,
+    pub fetching: bool,
+    pub cancelled: bool,
+    /// Owners collected and not resolved into members yet.
+    pub owners_count: U64,
+}
+
+/// Part of a NEP-171 token needed to count its owner.
+#[derive(Deserialize)]
+#[serde(crate = "near_sdk::serde")]
+struct TokenOwner {
+    owner_id: AccountId,
+}
+
+/// Owners of a page with their roles and tokens.
+struct OwnersPage {
+    owners: Vec<PagedMember>,
+    /// Number of owners, or tokens, the view returned. A page shorter than `page_size` is the last
+    /// one of its collection.
+    len: u64,
+    last_account_id: Option<AccountId>,
+    last_token_id: Option<String>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Stores a page of owners and moves to the next page, or to the next collection
+    /// once a page is not full. Collections that fail to return owners are fetched again
+    /// from the start with `nft_tokens`, unless the link has a minimum holding period, other
+    /// failed pages are fetched again by the next call.
+    /// `page` must be the latest requested page of the sync.
+    #[private]
+    pub fn on_owners_page(&mut self, link_id: LinkId, page: U64) -> SyncProgressView {
+        let link = self.expect_link(&link_id);
+        let mut progress = self.expect_sync_progress(&link_id);
+        require!(
+            progress.fetching && progress.requested_pages == page.0,
+            "Unexpected page of owners"
+        );
+        progress.fetching = false;
+        let page = match env::promise_result(0) {
+            PromiseResult::Failed
+                if !progress.use_enumeration
+                    && link.owners_view().supports_enumeration()
+                    && !link.needs_holders_since() =>
+            {
+                progress.use_enumeration = true;
+                progress.from_index = 0;
+                progress.from_account_id = None;
+                self.sync_progress.insert(&link_id, &progress);
+                return progress.into();
+            }
+            _ => parse_owners_page(&link, progress.use_enumeration),
+        };
+        let page = match page {
+            Ok(page) => page,
+            Err(error) => {
+                events::SyncFailed {
+                    link_id: &link_id,
+                    error: &error,
+                }
+                .emit();
+                self.sync_progress.insert(&link_id, &progress);
+                return progress.into();
+            }
+        };
+
+        let collection_index = progress.collection_index;
+        for (role, owner_id, tokens, since) in page.owners {
+            let key = (role, owner_id);
+            let mut tally = progress.owners.get(&key).unwrap_or(OwnerTally {
+                collections: 0,
+                tokens: 0,
+                last_collection_index: u64::MAX,
+                since: None,
+            });
+            // Owners that became holders in several collections count from the earliest one.
+            tally.since = match (tally.since, since) {
+                (Some(tally_since), Some(since)) => Some(tally_since.min(since)),
+                (tally_since, since) => tally_since.or(since),
+            };
+            if tally.last_collection_index != collection_index {
+                tally.collections += 1;
+                tally.tokens += tokens;
+                tally.last_collection_index = collection_index;
+            } else if progress.use_enumeration {
+                // Every token of the owner is listed separately
+                tally.tokens += tokens;
+            }
+            progress.owners.insert(&key, &tally);
+        }
+        if page.len < progress.page_size {
+            progress.collection_index += 1;
+            progress.from_index = 0;
+            progress.from_account_id = None;
+            progress.from_token_id = None;
+            progress.use_enumeration = false;
+        } else {
+            progress.from_index += progress.page_size;
+            progress.from_account_id = page.last_account_id;
+            progress.from_token_id = page.last_token_id;
+        }
+
+        self.sync_progress.insert(&link_id, &progress);
+        progress.into()
+    }
+
+    pub fn get_sync_progress(&self, link_id: LinkId) -> Option<SyncProgressView> {
+        self.sync_progress.get(&link_id).map(Into::into)
+    }
+
+    /// Abort a sync in progress of the link, refunding the caller bond.
+    /// Also recovers a sync whose fetch never called back, e.g. for lack of gas.
+    ///
+    /// Collected owners are removed `page_size` at a time. Returns true once the sync is removed,
+    /// otherwise the method needs to be called again.
+    pub fn cancel_sync(&mut self, link_id: LinkId) -> bool {
+        self.assert_owner();
+        let mut progress = self.expect_sync_progress(&link_id);
+        progress.cancelled = true;
+        progress.fetching = false;
+        remove_last_owners(&mut progress.owners, progress.page_size);
+        for _ in 0..progress.page_size {
+            if progress.members.pop().is_none() {
+                break;
+            }
+        }
+        if !progress.owners.is_empty() || !progress.members.is_empty() {
+            self.sync_progress.insert(&link_id, &progress);
+            return false;
+        }
+
+        self.sync_progress.remove(&link_id);
+        self.finish_sync(&link_id, false);
+        if progress.caller_bond > 0 {
+            self.release_held_bonds(progress.caller_bond);
+            Promise::new(progress.caller_id).transfer(progress.caller_bond);
+        }
+        true
+    }
+}
+
+impl Contract {
+    /// Continue the sync in progress of the link. Once every collection is fetched, resolves
+    /// the next `page_size` collected owners, otherwise fetches the next page.
+    pub(crate) fn continue_sync(
+        &mut self,
+        link_id: LinkId,
+        link: Link,
+    ) -> PromiseOrValue<SyncProgressView> {
+        let mut progress = self.expect_sync_progress(&link_id);
+        require!(!progress.cancelled, "Sync is being cancelled");
+        let fetched = progress.collection_index == link.nft_contract_ids.len() as u64;
+        if !fetched || progress.fetching || progress.owners.is_empty() {
+            let (caller_id, caller_bond) = (progress.caller_id, progress.caller_bond);
+            return PromiseOrValue::Promise(self.sync_next_page(
+                link_id,
+                link,
+                caller_id,
+                caller_bond,
+            ));
+        }
+
+        let min_collections = link
+            .membership_rule
+            .min_collections(link.nft_contract_ids.len()) as u64;
+        let members: Vec<PagedMember> =
+            remove_last_owners(&mut progress.owners, progress.page_size)
+                .into_iter()
+                .filter(|(_, tally)| tally.collections >= min_collections)
+                .map(|((role, owner_id), tally)| (role, owner_id, tally.tokens, tally.since))
+                .collect();
+        if !members.is_empty() {
+            progress.members.push(&members);
+        }
+        self.sync_progress.insert(&link_id, &progress);
+        PromiseOrValue::Value(progress.into())
+    }
+
+    /// Start a sync in pages of a link, or continue the one in progress.
+    /// Fetches the next page of owners, or DAO policy once owners of every collection are fetched.
+    /// Links without `page_size` are synced in pages of `DEFAULT_PAGE_SIZE` when some of their
+    /// collections don't implement the owners views.
+    pub(crate) fn sync_next_page(
+        &mut self,
+        link_id: LinkId,
+        link: Link,
+        caller_id: AccountId,
+        caller_bond: Balance,
+    ) -> Promise {
+        let mut progress = self
+            .sync_progress
+            .get(&link_id)
+            .unwrap_or_else(|| SyncProgress {
+                caller_id,
+                caller_bond,
+                collection_index: 0,
+                from_index: 0,
+                from_account_id: None,
+                from_token_id: None,
+                page_size: link
+                    .page_size
+                    .map(|size| size.0)
+                    .unwrap_or(DEFAULT_PAGE_SIZE),
+                use_enumeration: false,
+                fetching: false,
+                requested_pages: 0,
+                cancelled: false,
+                owners: UnorderedMap::new(StorageKey::SyncOwners {
+                    link_hash: env::sha256(link_id.as_bytes()),
+                }),
+                members: Vector::new(StorageKey::SyncMembers {
+                    link_hash: env::sha256(link_id.as_bytes()),
+                }),
+            });
+        require!(
+            !progress.fetching,
+            "Previous page of the sync is being fetched"
+        );
+        progress.fetching = true;
+        progress.requested_pages += 1;
+        self.sync_progress.insert(&link_id, &progress);
+
+        let collection_index = progress.collection_index as usize;
+        if collection_index == link.nft_contract_ids.len() {
+            return Promise::new(link.dao_contract_id)
+                .function_call(
+                    "get_policy".into(),
+                    vec![],
+                    0,
+                    Gas::ONE_TERA * TGAS_GET_DAO_POLICY,
+                )
+                .then(
+                    Self::ext(env::current_account_id()).handle_dao_members_sync(
+                        link_id,
+                        progress.caller_id,
+                        U128(progress.caller_bond),
+                    ),
+                );
+        }
+
+        let (method_name, args) = if progress.use_enumeration {
+            let args = json!({
+                "from_index": U128(progress.from_index as u128),
+                "limit": progress.page_size,
+            });
+            ("nft_tokens", args.to_string().into_bytes())
+        } else {
+            (
+                link.paged_owners_method().unwrap(),
+                link.owners_view().paged_args(
+                    progress.from_index,
+                    progress.from_account_id.as_ref(),
+                    progress.from_token_id.as_deref(),
+                    progress.page_size,
+                ),
+            )
+        };
+        Promise::new(link.nft_contract_ids[collection_index].clone())
+            .function_call(
+                method_name.into(),
+                args,
+                0,
+                Gas::ONE_TERA * TGAS_GET_NFT_TOKENS,
+            )
+            .then(
+                Self::ext(env::current_account_id())
+                    .on_owners_page(link_id, U64(progress.requested_pages)),
+            )
+    }
+
+    /// Remove the finished sync in pages of the link with the members it resolved.
+    pub(crate) fn remove_paged_sync(&mut self, link_id: &LinkId) {
+        if let Some(mut progress) = self.sync_progress.remove(link_id) {
+            progress.members.clear();
+        }
+    }
+
+    fn expect_sync_progress(&self, link_id: &LinkId) -> SyncProgress {
+        self.sync_progress
+            .get(link_id)
+            .unwrap_or_else(|| env::panic_str("No sync in progress"))
+    }
+}
+
+impl From<SyncProgress> for SyncProgressView {
+    fn from(progress: SyncProgress) -> Self {
+        Self {
+            caller_id: progress.caller_id,
+            collection_index: U64(progress.collection_index),
+            from_index: U64(progress.from_index),
+            from_account_id: progress.from_account_id,
+            from_token_id: progress.from_token_id,
+            use_enumeration: progress.use_enumeration,
+            fetching: progress.fetching,
+            cancelled: progress.cancelled,
+            owners_count: U64(progress.owners.len()),
+        }
+    }
+}
+
+/// Read the page of owners returned by the NFT view of `link`, or by `nft_tokens`.
+fn parse_owners_page(link: &Link, use_enumeration: bool) -> Result<OwnersPage, String> {
+    let error = "Failed to get NFT owners";
+    if use_enumeration {
+        let tokens: Vec<TokenOwner> = parse_promise_result(0, error)?;
+        return Ok(OwnersPage {
+            len: tokens.len() as u64,
+            owners: tokens
+                .into_iter()
+                .map(|token| (None, token.owner_id, 1, None))
+                .collect(),
+            last_account_id: None,
+            last_token_id: None,
+        });
+    }
+    if let RoleAssignment::Attribute { roles, .. } = &link.role_assignment {
+        let tokens: Vec<(String, AccountId, Option<String>)> = parse_promise_result(0, error)?;
+        return Ok(OwnersPage {
+            len: tokens.len() as u64,
+            last_account_id: None,
+            last_token_id: tokens.last().map(|(token_id, _, _)| token_id.clone()),
+            owners: tokens
+                .into_iter()
+                .filter_map(|(_, owner_id, value)| {
+                    let role = roles.get(&value?)?;
+                    Some((Some(role.clone()), owner_id, 1, None))
+                })
+                .collect(),
+        });
+    }
+    let owners: Vec<(AccountId, U128, Option<U64>)> = if link.needs_holders_since() {
+        parse_promise_result(0, error)?
+    } else {
+        let owners: Vec<(AccountId, U128)> = parse_promise_result(0, error)?;
+        owners
+            .into_iter()
+            .map(|(owner_id, tokens)| (owner_id, tokens, None))
+            .collect()
+    };
+    Ok(OwnersPage {
+        len: owners.len() as u64,
+        last_account_id: owners.last().map(|(owner_id, _, _)| owner_id.clone()),
+        last_token_id: None,
+        owners: owners
+            .into_iter()
+            .map(|(owner_id, tokens, since)| {
+                (None, owner_id, tokens.0 as u64, since.map(|since| since.0))
+            })
+            .collect(),
+    })
+}
+
+/// Remove up to `count` owners from the end of `owners`, which keeps removals cheap.
+fn remove_last_owners(
+    owners: &mut UnorderedMap<(Option<String>, AccountId), OwnerTally>,
+    count: u64,
+) -> Vec<((Option<String>, AccountId), OwnerTally)> {
+    let len = owners.len();
+    let last_owners: Vec<(Option<String>, AccountId)> = (len.saturating_sub(count)..len)
+        .rev()
+        .map(|index| owners.keys_as_vector().get(index).unwrap())
+        .collect();
+    last_owners
+        .into_iter()
+        .map(|owner_id| {
+            let tally = owners.remove(&owner_id).unwrap();
+            (owner_id, tally)
+        })
+        .collect()
+}
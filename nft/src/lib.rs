@@ -17,10 +17,18 @@ NOTES:
 */
 
 mod approve_impl;
+mod migrate;
 mod nft_impl;
+mod owner_index_impl;
+mod snapshot_impl;
+mod votes_impl;
 
 use std::collections::{HashMap, HashSet};
 
+use migrate::TokensMigration;
+use snapshot_impl::Snapshot;
+use votes_impl::{AccountCheckpoints, Checkpoint};
+
 use near_contract_standards::non_fungible_token::core::NonFungibleTokenCore;
 use near_contract_standards::non_fungible_token::metadata::{
     NFTContractMetadata, NonFungibleTokenMetadataProvider, TokenMetadata,
@@ -28,8 +36,10 @@ use near_contract_standards::non_fungible_token::metadata::{
 use near_contract_standards::non_fungible_token::NonFungibleToken;
 use near_contract_standards::non_fungible_token::{Token, TokenId};
 use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
-use near_sdk::collections::LazyOption;
+use near_sdk::collections::{LazyOption, LookupMap, TreeMap, UnorderedMap, Vector};
+use near_sdk::json_types::{U128, U64};
 use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::serde_json::{self, Value};
 use near_sdk::{
     env, ext_contract, near_bindgen, AccountId, BorshStorageKey, PanicOnDefault, PromiseOrValue,
 };
@@ -47,7 +57,26 @@ pub struct TokenMintArgs {
 pub struct Contract {
     tokens: NonFungibleToken,
     metadata: LazyOption<NFTContractMetadata>,
-    token_owners: HashSet<AccountId>,
+    /// Accounts holding at least one token with the number of tokens they hold, sorted by account id.
+    token_owners: TreeMap<AccountId, u64>,
+    /// Block timestamp when each account in `token_owners` was added to it.
+    holder_since: LookupMap<AccountId, u64>,
+    /// Accounts that delegated their voting power to another account.
+    delegates: LookupMap<AccountId, AccountId>,
+    /// Voting power of accounts with at least one vote.
+    votes: UnorderedMap<AccountId, u64>,
+    /// Latest changes of voting power of every account that ever had votes.
+    checkpoints: LookupMap<AccountId, AccountCheckpoints>,
+    total_supply_checkpoints: Vector<Checkpoint>,
+    snapshots: LookupMap<String, Snapshot>,
+    /// Snapshot being recorded. Tokens can't be minted, transferred or burned until it is complete,
+    /// so every chunk records holders as of the block the snapshot was created at.
+    recording_snapshot: Option<String>,
+    /// Transfers with `nft_transfer_call` waiting for `nft_resolve_transfer`, which may return
+    /// their tokens. A snapshot can't be created before they are resolved.
+    transfer_calls_in_flight: u64,
+    /// Counting of tokens minted before `migrate`, `None` once every token is counted.
+    tokens_migration: Option<TokensMigration>,
 }
 
 #[derive(BorshSerialize, BorshStorageKey)]
@@ -57,6 +86,15 @@ enum StorageKey {
     TokenMetadata,
     Enumeration,
     Approval,
+    Delegates,
+    Votes,
+    Checkpoints,
+    AccountCheckpoints { account_hash: Vec<u8> },
+    TotalSupplyCheckpoints,
+    Snapshots,
+    SnapshotHolders { name_hash: Vec<u8> },
+    HolderSince,
+    TokenOwners,
 }
 
 #[near_bindgen]
@@ -74,7 +112,16 @@ impl Contract {
                 Some(StorageKey::Approval),
             ),
             metadata: LazyOption::new(StorageKey::Metadata, Some(&metadata)),
-            token_owners: HashSet::new(),
+            token_owners: TreeMap::new(StorageKey::TokenOwners),
+            holder_since: LookupMap::new(StorageKey::HolderSince),
+            delegates: LookupMap::new(StorageKey::Delegates),
+            votes: UnorderedMap::new(StorageKey::Votes),
+            checkpoints: LookupMap::new(StorageKey::Checkpoints),
+            total_supply_checkpoints: Vector::new(StorageKey::TotalSupplyCheckpoints),
+            snapshots: LookupMap::new(StorageKey::Snapshots),
+            recording_snapshot: None,
+            transfer_calls_in_flight: 0,
+            tokens_migration: None,
         }
     }
 
@@ -98,32 +145,139 @@ impl Contract {
             self.tokens.owner_id,
             "Unauthorized"
         );
+        self.assert_tokens_not_frozen();
 
-        self.token_owners.insert(receiver_id.clone());
-        self.tokens
-            .internal_mint(token_id, receiver_id, Some(token_metadata))
+        self.move_token_votes(&token_id, None, Some(&receiver_id));
+        let token = self
+            .tokens
+            .internal_mint(token_id, receiver_id.clone(), Some(token_metadata));
+        self.update_owner_count(&receiver_id);
+        self.write_total_supply_checkpoint();
+        token
     }
 
     #[payable]
     pub fn multiple_nft_mint(&mut self, tokens_args: Vec<TokenMintArgs>) -> Vec<Token> {
         assert_eq!(self.tokens.owner_id, env::predecessor_account_id());
+        self.assert_tokens_not_frozen();
         let mut tokens = Vec::new();
 
         for args in tokens_args {
-            self.token_owners.insert(args.receiver_id.clone());
+            self.move_token_votes(&args.token_id, None, Some(&args.receiver_id));
             let token = self.tokens.internal_mint(
                 args.token_id,
-                args.receiver_id,
+                args.receiver_id.clone(),
                 Some(args.token_metadata),
             );
+            self.update_owner_count(&args.receiver_id);
             tokens.push(token);
         }
+        self.write_total_supply_checkpoint();
 
         tokens
     }
 
     pub fn nft_owners(&self) -> HashSet<AccountId> {
-        self.token_owners.clone()
+        self.token_owners
+            .iter()
+            .map(|(owner_id, _)| owner_id)
+            .collect()
+    }
+
+    /// Owners sorted by account id with the number of tokens they hold, `limit` of them following
+    /// `from_account_id`, or starting with the first owner, after skipping `from_index` owners.
+    /// Passing the last account id of a page as `from_account_id` of the next one doesn't skip
+    /// owners even if others leave in between, `from_index` jumps straight to a page.
+    pub fn nft_owners_paged(
+        &self,
+        from_index: Option<U64>,
+        from_account_id: Option<AccountId>,
+        limit: Option<U64>,
+    ) -> Vec<(AccountId, U128)> {
+        self.owners_page(from_index, from_account_id, limit)
+            .map(|(owner_id, count)| (owner_id, U128(count as u128)))
+            .collect()
+    }
+
+    /// Number of accounts holding at least one token.
+    pub fn nft_owners_count(&self) -> U64 {
+        U64(self.token_owners.len())
+    }
+
+    /// Number of tokens held by every owner.
+    pub fn nft_owner_counts(&self) -> HashMap<AccountId, U128> {
+        self.token_owners
+            .iter()
+            .map(|(owner_id, count)| (owner_id, U128(count as u128)))
+            .collect()
+    }
+
+    /// Block timestamp since which `account_id` has continuously held at least one token.
+    pub fn holder_since(&self, account_id: AccountId) -> Option<U64> {
+        self.holder_since.get(&account_id).map(U64)
+    }
+
+    /// Block timestamp since which every owner has continuously held at least one token.
+    pub fn nft_holders_since(&self) -> HashMap<AccountId, U64> {
+        self.token_owners
+            .iter()
+            .filter_map(|(owner_id, _)| {
+                let since = self.holder_since.get(&owner_id)?;
+                Some((owner_id, U64(since)))
+            })
+            .collect()
+    }
+
+    /// Owners as in `nft_owners_paged` with the block timestamp since which they have continuously
+    /// held at least one token, if known.
+    pub fn nft_holders_since_paged(
+        &self,
+        from_index: Option<U64>,
+        from_account_id: Option<AccountId>,
+        limit: Option<U64>,
+    ) -> Vec<(AccountId, U128, Option<U64>)> {
+        self.owners_page(from_index, from_account_id, limit)
+            .map(|(owner_id, count)| {
+                let since = self.holder_since.get(&owner_id).map(U64);
+                (owner_id, U128(count as u128), since)
+            })
+            .collect()
+    }
+
+    /// Owners grouped by the value of `attribute` in the JSON `extra` metadata of their tokens.
+    /// Tokens without the attribute are skipped, non-string values are grouped by their JSON text.
+    /// Goes through every token, use `nft_owners_by_attribute_paged` for large collections.
+    pub fn nft_owners_by_attribute(
+        &self,
+        attribute: String,
+    ) -> HashMap<String, HashSet<AccountId>> {
+        let mut owners: HashMap<String, HashSet<AccountId>> = HashMap::new();
+        for (token_id, owner_id) in self.tokens.owner_by_id.iter() {
+            if let Some(value) = self.attribute_value(&token_id, &attribute) {
+                owners.entry(value).or_default().insert(owner_id);
+            }
+        }
+        owners
+    }
+
+    /// `limit` tokens following `from_token_id` (in `nft_tokens` order), or starting with the first
+    /// token, with their owners and values of `attribute` as in `nft_owners_by_attribute`.
+    /// Tokens without the attribute have no value. Passing the last token id of a page as
+    /// `from_token_id` of the next one pages through every token.
+    pub fn nft_owners_by_attribute_paged(
+        &self,
+        attribute: String,
+        from_token_id: Option<TokenId>,
+        limit: Option<U64>,
+    ) -> Vec<(TokenId, AccountId, Option<String>)> {
+        let limit = limit.map(|limit| limit.0).unwrap_or(u64::MAX);
+        iter_after(&self.tokens.owner_by_id, from_token_id)
+            .take(limit as usize)
+            .map(|(token_id, owner_id)| {
+                let value = self.attribute_value(&token_id, &attribute);
+                (token_id, owner_id, value)
+            })
+            .collect()
     }
 
     pub fn set_owner(&mut self, account_id: AccountId) {
@@ -133,22 +287,68 @@ impl Contract {
 }
 
 impl Contract {
-    pub fn check_old_owner_in_map(&mut self, owner_id: &AccountId) {
-        let owner_nft = self
+    /// Set the number of tokens of `owner_id` in the owner index from the tokens it holds,
+    /// removing it from the index once it holds none.
+    pub fn update_owner_count(&mut self, owner_id: &AccountId) {
+        let count = self.tokens_count(owner_id);
+        if count == 0 {
+            if self.token_owners.remove(owner_id).is_some() {
+                self.holder_since.remove(owner_id);
+            }
+        } else if self.token_owners.insert(owner_id, &count).is_none() {
+            self.holder_since.insert(owner_id, &env::block_timestamp());
+        }
+    }
+
+    /// Page of the owner index for `nft_owners_paged` and `nft_holders_since_paged`.
+    fn owners_page(
+        &self,
+        from_index: Option<U64>,
+        from_account_id: Option<AccountId>,
+        limit: Option<U64>,
+    ) -> impl Iterator<Item = (AccountId, u64)> + '_ {
+        let from_index = from_index.map(|index| index.0).unwrap_or(0);
+        let limit = limit.map(|limit| limit.0).unwrap_or(u64::MAX);
+        iter_after(&self.token_owners, from_account_id)
+            .skip(from_index as usize)
+            .take(limit as usize)
+    }
+
+    fn attribute_value(&self, token_id: &TokenId, attribute: &str) -> Option<String> {
+        let value = self
             .tokens
-            .tokens_per_owner
+            .token_metadata_by_id
             .as_ref()
             .unwrap()
-            .get(&owner_id);
-
-        if owner_nft.is_none() {
-            self.token_owners.remove(owner_id);
+            .get(token_id)
+            .and_then(|metadata| metadata.extra)
+            .and_then(|extra| serde_json::from_str::<Value>(&extra).ok())
+            .and_then(|mut extra| extra.get_mut(attribute).map(Value::take));
+        match value {
+            None | Some(Value::Null) => None,
+            Some(Value::String(value)) => Some(value),
+            Some(value) => Some(value.to_string()),
         }
     }
 
-    pub fn update_owners_map(&mut self, previous_owner: &AccountId, new_owner: AccountId) {
-        self.check_old_owner_in_map(previous_owner);
-        self.token_owners.insert(new_owner);
+    pub fn update_owners_map(&mut self, previous_owner: &AccountId, new_owner: &AccountId) {
+        self.update_owner_count(previous_owner);
+        self.update_owner_count(new_owner);
+    }
+}
+
+/// Entries of `map` following `from_key`, or all of them.
+fn iter_after<K, V>(
+    map: &TreeMap<K, V>,
+    from_key: Option<K>,
+) -> Box<dyn Iterator<Item = (K, V)> + '_>
+where
+    K: Ord + Clone + BorshSerialize + BorshDeserialize,
+    V: BorshSerialize + BorshDeserialize,
+{
+    match from_key {
+        Some(from_key) => Box::new(map.iter_from(from_key)),
+        None => Box::new(map.iter()),
     }
 }
 
@@ -164,7 +364,7 @@ impl NonFungibleTokenMetadataProvider for Contract {
 #[cfg(all(test, not(target_arch = "wasm32")))]
 mod tests {
     use near_contract_standards::non_fungible_token::approval::NonFungibleTokenApproval;
-    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::test_utils::{accounts, get_logs, VMContextBuilder};
     use near_sdk::testing_env;
     use std::collections::HashMap;
 
@@ -424,7 +624,570 @@ mod tests {
         }
         let tokens = contract.multiple_nft_mint(token_args);
         assert_eq!(contract.token_owners.len(), 6);
+        assert_eq!(contract.token_owners.get(&accounts(1)), Some(1));
         assert_eq!(tokens.len(), 6);
         assert_eq!(contract.tokens.nft_tokens(None, None).len(), 6);
     }
+
+    #[test]
+    fn test_owner_counts() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 3)
+            .predecessor_account_id(accounts(0))
+            .build());
+        let token_args = (0..3)
+            .map(|i| TokenMintArgs {
+                token_id: i.to_string(),
+                receiver_id: if i < 2 { accounts(1) } else { accounts(2) },
+                token_metadata: sample_token_metadata(),
+            })
+            .collect();
+        contract.multiple_nft_mint(token_args);
+
+        let counts = contract.nft_owner_counts();
+        assert_eq!(counts.len(), 2);
+        assert_eq!(counts[&accounts(1)], U128(2));
+        assert_eq!(counts[&accounts(2)], U128(1));
+
+        assert_eq!(
+            contract.nft_owners_paged(None, None, Some(U64(1))),
+            vec![(accounts(1), U128(2))]
+        );
+        assert_eq!(
+            contract.nft_owners_paged(None, Some(accounts(1)), Some(U64(5))),
+            vec![(accounts(2), U128(1))]
+        );
+        assert_eq!(
+            contract.nft_owners_paged(Some(U64(1)), None, Some(U64(5))),
+            vec![(accounts(2), U128(1))]
+        );
+        assert_eq!(contract.nft_owners_count(), U64(2));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.nft_transfer(accounts(2), "0".into(), None, None);
+        contract.nft_transfer(accounts(3), "1".into(), None, None);
+        assert_eq!(
+            contract.nft_owners_paged(None, None, None),
+            vec![(accounts(2), U128(2)), (accounts(3), U128(1))]
+        );
+        // A page following an owner that left starts with the next owner
+        assert_eq!(
+            contract.nft_owners_paged(None, Some(accounts(1)), Some(U64(1))),
+            vec![(accounts(2), U128(2))]
+        );
+        assert_eq!(contract.nft_owners_count(), U64(2));
+    }
+
+    #[test]
+    fn test_owners_by_attribute() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 4)
+            .predecessor_account_id(accounts(0))
+            .build());
+        let extras = [
+            Some(r#"{"clan":"red"}"#),
+            Some(r#"{"clan":"blue","level":3}"#),
+            Some(r#"{"level":1}"#),
+            None,
+        ];
+        let token_args = extras
+            .iter()
+            .enumerate()
+            .map(|(i, extra)| TokenMintArgs {
+                token_id: i.to_string(),
+                receiver_id: if i == 0 { accounts(1) } else { accounts(2) },
+                token_metadata: TokenMetadata {
+                    extra: extra.map(str::to_string),
+                    ..sample_token_metadata()
+                },
+            })
+            .collect();
+        contract.multiple_nft_mint(token_args);
+
+        let clans = contract.nft_owners_by_attribute("clan".into());
+        assert_eq!(clans.len(), 2);
+        assert_eq!(clans["red"], vec![accounts(1)].into_iter().collect());
+        assert_eq!(clans["blue"], vec![accounts(2)].into_iter().collect());
+
+        let levels = contract.nft_owners_by_attribute("level".into());
+        assert_eq!(levels.len(), 2);
+        assert!(levels.contains_key("1") && levels.contains_key("3"));
+
+        let page = contract.nft_owners_by_attribute_paged("clan".into(), None, Some(U64(2)));
+        assert_eq!(
+            page,
+            vec![
+                ("0".to_string(), accounts(1), Some("red".to_string())),
+                ("1".to_string(), accounts(2), Some("blue".to_string())),
+            ]
+        );
+        let page = contract.nft_owners_by_attribute_paged("clan".into(), Some("1".into()), None);
+        assert_eq!(
+            page,
+            vec![
+                ("2".to_string(), accounts(2), None),
+                ("3".to_string(), accounts(2), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_delegated_votes_follow_transfers() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 2)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("1".into(), accounts(1), sample_token_metadata());
+        assert_eq!(contract.get_votes(accounts(1)), U128(2));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.delegate(accounts(3));
+        assert_eq!(contract.delegates(accounts(1)), accounts(3));
+        assert_eq!(contract.get_votes(accounts(1)), U128(0));
+        assert_eq!(contract.get_votes(accounts(3)), U128(2));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.nft_transfer(accounts(2), "0".into(), None, None);
+        assert_eq!(contract.get_votes(accounts(2)), U128(1));
+        assert_eq!(contract.get_votes(accounts(3)), U128(1));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.delegate(accounts(1));
+        assert_eq!(contract.delegates(accounts(1)), accounts(1));
+        let votes = contract.nft_votes();
+        assert_eq!(votes.len(), 2);
+        assert_eq!(votes[&accounts(1)], U128(1));
+        assert_eq!(votes[&accounts(2)], U128(1));
+    }
+
+    #[test]
+    fn test_past_votes() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 2)
+            .predecessor_account_id(accounts(0))
+            .block_index(10)
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("1".into(), accounts(1), sample_token_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .block_index(20)
+            .build());
+        contract.nft_transfer(accounts(2), "0".into(), None, None);
+
+        testing_env!(context.block_index(30).is_view(true).build());
+        assert_eq!(contract.get_past_votes(accounts(1), U64(5)), U128(0));
+        assert_eq!(contract.get_past_votes(accounts(1), U64(15)), U128(2));
+        assert_eq!(contract.get_past_votes(accounts(1), U64(25)), U128(1));
+        assert_eq!(contract.get_past_votes(accounts(2), U64(15)), U128(0));
+        assert_eq!(contract.get_past_votes(accounts(2), U64(20)), U128(1));
+        assert_eq!(contract.get_past_total_supply(U64(5)), U128(0));
+        assert_eq!(contract.get_past_total_supply(U64(29)), U128(2));
+    }
+
+    #[test]
+    fn test_past_votes_history_is_kept_for_retention_period() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST)
+            .predecessor_account_id(accounts(0))
+            .block_index(10)
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+
+        // Every transfer back and forth adds a checkpoint for both accounts
+        let transfer = |contract: &mut Contract, context: &mut VMContextBuilder, block_index| {
+            let (from, to) = if block_index % 2 == 1 {
+                (accounts(1), accounts(2))
+            } else {
+                (accounts(2), accounts(1))
+            };
+            testing_env!(context
+                .is_view(false)
+                .attached_deposit(1)
+                .predecessor_account_id(from)
+                .block_index(block_index)
+                .build());
+            contract.nft_transfer(to, "0".into(), None, None);
+        };
+        for block_index in 11..111 {
+            transfer(&mut contract, &mut context, block_index);
+        }
+
+        // Any number of recent changes keeps the history of the retention period
+        testing_env!(context.block_index(200).is_view(true).build());
+        assert_eq!(contract.get_past_votes(accounts(1), U64(10)), U128(1));
+        assert_eq!(contract.get_past_votes(accounts(1), U64(11)), U128(0));
+        assert_eq!(contract.get_past_votes(accounts(2), U64(10)), U128(0));
+        assert_eq!(contract.get_past_votes(accounts(2), U64(11)), U128(1));
+        assert_eq!(contract.get_past_votes(accounts(1), U64(199)), U128(1));
+
+        // Once they expire, checkpoints are removed but the one in force when the period starts
+        let retention = votes_impl::CHECKPOINT_RETENTION_BLOCKS;
+        for block_index in 11 + retention..111 + retention {
+            transfer(&mut contract, &mut context, block_index);
+        }
+        testing_env!(context.block_index(200 + retention).is_view(true).build());
+        assert!(contract.checkpoints.get(&accounts(1)).unwrap().is_pruned());
+        assert_eq!(contract.get_past_votes(accounts(1), U64(110)), U128(1));
+        assert_eq!(contract.get_past_votes(accounts(2), U64(110)), U128(0));
+        assert_eq!(
+            contract.get_past_votes(accounts(1), U64(11 + retention)),
+            U128(0)
+        );
+        assert_eq!(contract.get_past_total_supply(U64(10)), U128(1));
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 3)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("1".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("2".into(), accounts(2), sample_token_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST)
+            .block_index(42)
+            .build());
+        let info = contract.snapshot_create("season-2".into(), Some(U64(1)));
+        assert_eq!(info.block_height, U64(42));
+        assert_eq!(info.holders_count, U64(1));
+        assert!(!info.complete);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST)
+            .block_index(43)
+            .build());
+        let info = contract.snapshot_continue("season-2".into(), Some(U64(1)));
+        assert_eq!(info.holders_count, U64(2));
+        assert!(!info.complete);
+        assert_eq!(contract.recording_snapshot, Some("season-2".to_string()));
+        let info = contract.snapshot_continue("season-2".into(), Some(U64(1)));
+        assert_eq!(info.block_height, U64(42));
+        assert_eq!(info.holders_count, U64(2));
+        assert!(info.complete);
+        assert_eq!(contract.recording_snapshot, None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .build());
+        // Tokens move again once the snapshot is complete
+        contract.nft_transfer(accounts(3), "0".into(), None, None);
+
+        let mut holders = contract.snapshot_holders("season-2".into(), None, None);
+        holders.sort();
+        assert_eq!(
+            holders,
+            vec![(accounts(1), U128(2)), (accounts(2), U128(1))]
+        );
+        assert_eq!(
+            contract
+                .snapshot_holders("season-2".into(), Some(U64(1)), Some(U64(5)))
+                .len(),
+            1
+        );
+        assert!(contract.snapshot_info("season-3".into()).is_none());
+    }
+
+    #[test]
+    fn test_holder_since() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 2)
+            .predecessor_account_id(accounts(0))
+            .block_timestamp(10)
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .block_timestamp(20)
+            .build());
+        contract.nft_mint("1".into(), accounts(1), sample_token_metadata());
+        assert_eq!(contract.holder_since(accounts(1)), Some(U64(10)));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(1))
+            .block_timestamp(30)
+            .build());
+        contract.nft_transfer(accounts(2), "0".into(), None, None);
+        contract.nft_transfer(accounts(2), "1".into(), None, None);
+
+        assert_eq!(contract.holder_since(accounts(1)), None);
+        let holders_since = contract.nft_holders_since();
+        assert_eq!(holders_since.len(), 1);
+        assert_eq!(holders_since[&accounts(2)], U64(30));
+        assert_eq!(
+            contract.nft_holders_since_paged(None, None, Some(U64(1))),
+            vec![(accounts(2), U128(2), Some(U64(30)))]
+        );
+    }
+
+    #[test]
+    fn test_approved_transfers_update_owners() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 2)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("1".into(), accounts(1), sample_token_metadata());
+
+        // accounts(1) lists both tokens on marketplace accounts(3)
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.nft_approve("0".into(), accounts(3), None);
+        testing_env!(context.storage_usage(env::storage_usage()).build());
+        contract.nft_approve("1".into(), accounts(3), None);
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(3))
+            .build());
+        contract.nft_transfer(accounts(2), "0".into(), Some(1), None);
+        assert_eq!(
+            contract.nft_owners(),
+            HashSet::from([accounts(1), accounts(2)])
+        );
+
+        contract.nft_transfer_payout(accounts(4), "1".into(), 1, U128(100), 10);
+        assert_eq!(
+            contract.nft_owners(),
+            HashSet::from([accounts(2), accounts(4)])
+        );
+        assert_eq!(contract.holder_since(accounts(1)), None);
+    }
+
+    #[test]
+    fn test_rebuild_owner_index() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 3)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("1".into(), accounts(2), sample_token_metadata());
+        contract.nft_mint("2".into(), accounts(2), sample_token_metadata());
+
+        // Drift left by transfers that didn't update the index
+        contract.token_owners.remove(&accounts(2));
+        contract.token_owners.insert(&accounts(3), &1);
+        contract.token_owners.insert(&accounts(1), &5);
+
+        let mismatches =
+            contract.owner_index_mismatches(Some("0".into()), Some(accounts(1)), Some(U64(2)));
+        assert_eq!(mismatches.missing, vec![accounts(2)]);
+        assert_eq!(mismatches.stale, vec![accounts(3)]);
+        assert!(mismatches.miscounted.is_empty());
+        assert_eq!(mismatches.last_token_id, Some("2".to_string()));
+        assert_eq!(mismatches.last_account_id, None);
+
+        let mismatches = contract.owner_index_mismatches(None, None, Some(U64(1)));
+        assert!(mismatches.missing.is_empty());
+        assert_eq!(mismatches.miscounted, vec![accounts(1)]);
+        assert_eq!(mismatches.last_token_id, Some("0".to_string()));
+        assert_eq!(mismatches.last_account_id, Some(accounts(1)));
+
+        let repaired = contract.rebuild_owner_index(None, None, None);
+        assert_eq!(repaired.missing, vec![accounts(2)]);
+        assert_eq!(repaired.stale, vec![accounts(3)]);
+        assert_eq!(repaired.miscounted, vec![accounts(1)]);
+        assert_eq!(
+            contract.nft_owner_counts(),
+            HashMap::from([(accounts(1), U128(1)), (accounts(2), U128(2))])
+        );
+
+        let mismatches = contract.owner_index_mismatches(None, None, None);
+        assert!(mismatches.missing.is_empty() && mismatches.stale.is_empty());
+        assert!(mismatches.miscounted.is_empty());
+    }
+
+    #[test]
+    fn test_migrate() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 3)
+            .predecessor_account_id(accounts(0))
+            .build());
+        // Minted the way the old contract did, without an owner index or votes
+        for (token_id, owner_id) in [("0", accounts(1)), ("1", accounts(2)), ("2", accounts(2))] {
+            contract
+                .tokens
+                .internal_mint(token_id.into(), owner_id, Some(sample_token_metadata()));
+        }
+        // The old index misses the owner of tokens "1" and "2"
+        env::state_write(&migrate::OldContract {
+            tokens: contract.tokens,
+            metadata: contract.metadata,
+            token_owners: HashSet::from([accounts(1)]),
+        });
+
+        testing_env!(context.block_index(10).build());
+        let mut contract = Contract::migrate();
+        assert!(!contract.migrate_tokens(Some(U64(2))));
+        assert_eq!(
+            contract.nft_owner_counts(),
+            HashMap::from([(accounts(1), U128(1)), (accounts(2), U128(2))])
+        );
+
+        // Token "2" is not counted yet, so its vote is given to the new owner by the migration
+        testing_env!(context
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.nft_transfer(accounts(3), "2".to_string(), None, None);
+        assert_eq!(contract.get_votes(accounts(2)), U128(1));
+        assert_eq!(contract.get_votes(accounts(3)), U128(0));
+
+        testing_env!(context
+            .attached_deposit(0)
+            .predecessor_account_id(accounts(0))
+            .build());
+        assert!(contract.migrate_tokens(None));
+        for account_id in [accounts(1), accounts(2), accounts(3)] {
+            assert_eq!(contract.get_votes(account_id), U128(1));
+        }
+
+        testing_env!(context
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(2))
+            .build());
+        contract.nft_transfer(accounts(1), "1".to_string(), None, None);
+        assert_eq!(contract.get_votes(accounts(1)), U128(2));
+        assert_eq!(contract.get_votes(accounts(2)), U128(0));
+        assert_eq!(
+            contract.nft_owner_counts(),
+            HashMap::from([(accounts(1), U128(2)), (accounts(3), U128(1))])
+        );
+
+        testing_env!(context.block_index(11).build());
+        assert_eq!(contract.get_past_total_supply(U64(10)), U128(3));
+    }
+
+    #[test]
+    fn test_burn() {
+        let mut context = get_context(accounts(0));
+        testing_env!(context.build());
+        let mut contract = Contract::new(accounts(0), nft_contract_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST * 3)
+            .predecessor_account_id(accounts(0))
+            .build());
+        contract.nft_mint("0".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("1".into(), accounts(1), sample_token_metadata());
+        contract.nft_mint("2".into(), accounts(2), sample_token_metadata());
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(MINT_STORAGE_COST)
+            .predecessor_account_id(accounts(1))
+            .build());
+        contract.nft_approve("0".into(), accounts(3), None);
+
+        // The approved account burns a token of accounts(1)
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .attached_deposit(1)
+            .predecessor_account_id(accounts(3))
+            .block_index(1)
+            .build());
+        contract.nft_burn("0".into());
+        assert!(contract.nft_token("0".into()).is_none());
+        assert_eq!(contract.get_votes(accounts(1)), U128(1));
+        assert_eq!(contract.nft_owner_counts()[&accounts(1)], U128(1));
+        assert!(get_logs()[0].contains(r#""event":"nft_burn""#));
+
+        testing_env!(context
+            .storage_usage(env::storage_usage())
+            .predecessor_account_id(accounts(2))
+            .block_index(2)
+            .build());
+        contract.nft_burn("2".into());
+        assert_eq!(contract.nft_owners(), HashSet::from([accounts(1)]));
+        assert_eq!(contract.holder_since(accounts(2)), None);
+        assert_eq!(contract.nft_supply_for_owner(accounts(2)), U128(0));
+
+        testing_env!(context.block_index(3).build());
+        assert_eq!(contract.nft_total_supply(), U128(1));
+        assert_eq!(contract.get_past_total_supply(U64(2)), U128(1));
+    }
 }
@@ -0,0 +1,98 @@
+use crate::*;
+
+/// State of contracts deployed before the owner index was moved out of the root state.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct OldContract {
+    pub tokens: NonFungibleToken,
+    pub metadata: LazyOption<NFTContractMetadata>,
+    pub token_owners: HashSet<AccountId>,
+}
+
+/// Progress of `migrate_tokens`.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct TokensMigration {
+    /// Last counted token, tokens following it have no votes yet.
+    pub last_token_id: Option<TokenId>,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Convert the state of a contract deployed with the old `HashSet` owner index.
+    ///
+    /// The old index misses owners, so the owner index and voting power are counted from tokens
+    /// by `migrate_tokens` instead. Until it is done, transfers and burns of tokens not counted yet
+    /// don't move votes, and delegation is disabled.
+    #[private]
+    #[init(ignore_state)]
+    pub fn migrate() -> Self {
+        let old: OldContract =
+            env::state_read().unwrap_or_else(|| env::panic_str("Old state doesn't exist"));
+        Self {
+            tokens: old.tokens,
+            metadata: old.metadata,
+            token_owners: TreeMap::new(StorageKey::TokenOwners),
+            holder_since: LookupMap::new(StorageKey::HolderSince),
+            delegates: LookupMap::new(StorageKey::Delegates),
+            votes: UnorderedMap::new(StorageKey::Votes),
+            checkpoints: LookupMap::new(StorageKey::Checkpoints),
+            total_supply_checkpoints: Vector::new(StorageKey::TotalSupplyCheckpoints),
+            snapshots: LookupMap::new(StorageKey::Snapshots),
+            recording_snapshot: None,
+            transfer_calls_in_flight: 0,
+            tokens_migration: Some(TokensMigration {
+                last_token_id: None,
+            }),
+        }
+    }
+
+    /// Count `limit` more tokens after `migrate`: give their votes to their current owners and
+    /// add the owners to the owner index, as holders since this call.
+    /// Returns true once every token is counted.
+    pub fn migrate_tokens(&mut self, limit: Option<U64>) -> bool {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.tokens.owner_id,
+            "Unauthorized"
+        );
+        let migration = self
+            .tokens_migration
+            .take()
+            .unwrap_or_else(|| env::panic_str("Tokens are already migrated"));
+        let limit = limit.map(|limit| limit.0).unwrap_or(u64::MAX) as usize;
+
+        let tokens: Vec<(TokenId, AccountId)> =
+            iter_after(&self.tokens.owner_by_id, migration.last_token_id)
+                .take(limit)
+                .collect();
+        let owners: HashSet<&AccountId> = tokens.iter().map(|(_, owner_id)| owner_id).collect();
+        for owner_id in owners {
+            self.update_owner_count(owner_id);
+        }
+        for (_, owner_id) in &tokens {
+            self.move_votes(None, Some(owner_id), 1);
+        }
+
+        if tokens.len() < limit {
+            self.write_total_supply_checkpoint();
+            return true;
+        }
+        self.tokens_migration = Some(TokensMigration {
+            last_token_id: tokens.last().map(|(token_id, _)| token_id.clone()),
+        });
+        false
+    }
+}
+
+impl Contract {
+    /// Whether the vote of `token_id` is counted, which tokens minted before `migrate` only are
+    /// once `migrate_tokens` reaches them.
+    pub(crate) fn is_token_counted(&self, token_id: &TokenId) -> bool {
+        match &self.tokens_migration {
+            None => true,
+            Some(migration) => migration
+                .last_token_id
+                .as_ref()
+                .is_some_and(|last_token_id| token_id <= last_token_id),
+        }
+    }
+}
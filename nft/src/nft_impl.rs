@@ -1,7 +1,9 @@
 use near_contract_standards::non_fungible_token::{
-    core::NonFungibleTokenResolver, events::NftTransfer, refund_approved_account_ids,
+    core::NonFungibleTokenResolver,
+    events::{NftBurn, NftTransfer},
+    refund_approved_account_ids,
 };
-use near_sdk::{assert_one_yocto, json_types::U128, PromiseResult};
+use near_sdk::{assert_one_yocto, json_types::U128, require, Balance, Promise, PromiseResult};
 
 use crate::*;
 
@@ -21,9 +23,13 @@ impl NonFungibleTokenCore for Contract {
         approval_id: Option<u64>,
         memo: Option<String>,
     ) {
+        self.assert_tokens_not_frozen();
+        let previous_owner_id = self.expect_token_owner(&token_id);
         self.tokens
-            .nft_transfer(receiver_id.clone(), token_id, approval_id, memo);
-        self.update_owners_map(&env::predecessor_account_id(), receiver_id);
+            .nft_transfer(receiver_id.clone(), token_id.clone(), approval_id, memo);
+        self.move_token_votes(&token_id, Some(&previous_owner_id), Some(&receiver_id));
+        // The caller may be an approved account rather than the owner
+        self.update_owners_map(&previous_owner_id, &receiver_id);
     }
 
     #[payable]
@@ -35,6 +41,10 @@ impl NonFungibleTokenCore for Contract {
         memo: Option<String>,
         msg: String,
     ) -> PromiseOrValue<bool> {
+        self.assert_tokens_not_frozen();
+        let previous_owner_id = self.expect_token_owner(&token_id);
+        self.move_token_votes(&token_id, Some(&previous_owner_id), Some(&receiver_id));
+        self.transfer_calls_in_flight += 1;
         self.tokens
             .nft_transfer_call(receiver_id, token_id, approval_id, memo, msg)
     }
@@ -67,10 +77,12 @@ impl NonFungibleTokenResolver for Contract {
             }
             PromiseResult::Failed => true,
         };
+        // Saturating, transfers started before the counter was added are not counted
+        self.transfer_calls_in_flight = self.transfer_calls_in_flight.saturating_sub(1);
 
         // if call succeeded, return early
         if !must_revert {
-            self.update_owners_map(&previous_owner_id, receiver_id.clone());
+            self.update_owners_map(&previous_owner_id, &receiver_id);
             return true;
         }
 
@@ -79,8 +91,8 @@ impl NonFungibleTokenResolver for Contract {
         // Check that receiver didn't already transfer it away or burn it.
         if let Some(current_owner) = self.tokens.owner_by_id.get(&token_id) {
             if current_owner != receiver_id {
-                self.check_old_owner_in_map(&previous_owner_id);
-                self.check_old_owner_in_map(&receiver_id);
+                self.update_owner_count(&previous_owner_id);
+                self.update_owner_count(&receiver_id);
                 // The token is not owned by the receiver anymore. Can't return it.
                 return true;
             }
@@ -90,13 +102,14 @@ impl NonFungibleTokenResolver for Contract {
             if let Some(approved_account_ids) = approved_account_ids {
                 refund_approved_account_ids(previous_owner_id.clone(), &approved_account_ids);
             }
-            self.check_old_owner_in_map(&previous_owner_id);
-            self.check_old_owner_in_map(&receiver_id);
+            self.update_owner_count(&previous_owner_id);
+            self.update_owner_count(&receiver_id);
             return true;
         };
 
         self.tokens
             .internal_transfer_unguarded(&token_id, &receiver_id, &previous_owner_id);
+        self.move_token_votes(&token_id, Some(&receiver_id), Some(&previous_owner_id));
 
         // If using Approval Management extension,
         // 1. revert any approvals receiver already set, refunding storage costs
@@ -136,6 +149,7 @@ impl Contract {
         max_len_payout: u32,
     ) -> HashMap<AccountId, U128> {
         assert_one_yocto();
+        self.assert_tokens_not_frozen();
 
         let owner_id = self.tokens.owner_by_id.get(&token_id).expect("No token id");
         self.tokens.nft_transfer(
@@ -144,11 +158,83 @@ impl Contract {
             Some(approval_id),
             None,
         );
+        self.move_token_votes(&token_id, Some(&owner_id), Some(&receiver_id));
+        self.update_owners_map(&owner_id, &receiver_id);
 
         let mut result = HashMap::new();
         result.insert(owner_id, balance);
         result
     }
+
+    /// Burn `token_id`. Can be called by the token owner or an account approved for the token.
+    /// Storage released by the token and its approvals is refunded to the owner.
+    #[payable]
+    pub fn nft_burn(&mut self, token_id: TokenId) {
+        assert_one_yocto();
+        self.assert_tokens_not_frozen();
+        let owner_id = self.expect_token_owner(&token_id);
+        let sender_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+
+        let approved_account_ids = self
+            .tokens
+            .approvals_by_id
+            .as_mut()
+            .and_then(|by_id| by_id.remove(&token_id));
+        let authorized_id = if sender_id == owner_id {
+            None
+        } else {
+            require!(
+                approved_account_ids
+                    .as_ref()
+                    .is_some_and(|ids| ids.contains_key(&sender_id)),
+                "Sender not approved"
+            );
+            Some(&sender_id)
+        };
+        if let Some(next_approval_id_by_id) = &mut self.tokens.next_approval_id_by_id {
+            next_approval_id_by_id.remove(&token_id);
+        }
+        self.tokens.owner_by_id.remove(&token_id);
+        if let Some(tokens_per_owner) = &mut self.tokens.tokens_per_owner {
+            let mut owner_tokens = tokens_per_owner.get(&owner_id).unwrap();
+            owner_tokens.remove(&token_id);
+            if owner_tokens.is_empty() {
+                tokens_per_owner.remove(&owner_id);
+            } else {
+                tokens_per_owner.insert(&owner_id, &owner_tokens);
+            }
+        }
+        if let Some(token_metadata_by_id) = &mut self.tokens.token_metadata_by_id {
+            token_metadata_by_id.remove(&token_id);
+        }
+
+        self.move_token_votes(&token_id, Some(&owner_id), None);
+        self.update_owner_count(&owner_id);
+        self.write_total_supply_checkpoint();
+
+        let storage_released = initial_storage_usage.saturating_sub(env::storage_usage());
+        if storage_released > 0 {
+            Promise::new(owner_id.clone())
+                .transfer(Balance::from(storage_released) * env::storage_byte_cost());
+        }
+        NftBurn {
+            owner_id: &owner_id,
+            token_ids: &[&token_id],
+            authorized_id,
+            memo: None,
+        }
+        .emit();
+    }
+}
+
+impl Contract {
+    fn expect_token_owner(&self, token_id: &TokenId) -> AccountId {
+        self.tokens
+            .owner_by_id
+            .get(token_id)
+            .unwrap_or_else(|| env::panic_str("Token not found"))
+    }
 }
 
 /// Used when an NFT is transferred using `nft_transfer_call`. This trait is implemented on the receiving contract, not on the NFT contract.
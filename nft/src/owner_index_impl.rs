@@ -0,0 +1,100 @@
+use std::collections::BTreeSet;
+
+use crate::*;
+
+/// Differences between the owner index (`token_owners`) and token ownership.
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct OwnerIndexMismatches {
+    /// Owners of checked tokens that are missing from the index.
+    pub missing: Vec<AccountId>,
+    /// Checked accounts of the index that don't own any tokens.
+    pub stale: Vec<AccountId>,
+    /// Checked accounts of the index with a wrong number of tokens.
+    pub miscounted: Vec<AccountId>,
+    /// Last checked token, `from_token_id` of the next call. `None` once every token is checked.
+    pub last_token_id: Option<TokenId>,
+    /// Last checked account of the index, `from_account_id` of the next call. `None` once every
+    /// account is checked.
+    pub last_account_id: Option<AccountId>,
+}
+
+/// Audit and repair of the owner index, which is updated separately from token ownership on
+/// every mint and transfer.
+///
+/// Both methods check `limit` tokens following `from_token_id` (in `nft_tokens` order) and `limit`
+/// accounts of the index following `from_account_id`. The whole contract is covered by passing
+/// `last_token_id` and `last_account_id` of a call to the next one until both are `None`.
+#[near_bindgen]
+impl Contract {
+    pub fn owner_index_mismatches(
+        &self,
+        from_token_id: Option<TokenId>,
+        from_account_id: Option<AccountId>,
+        limit: Option<U64>,
+    ) -> OwnerIndexMismatches {
+        let limit = limit.map(|limit| limit.0).unwrap_or(u64::MAX) as usize;
+        let tokens_per_owner = self.tokens.tokens_per_owner.as_ref().unwrap();
+
+        let tokens: Vec<(TokenId, AccountId)> = iter_after(&self.tokens.owner_by_id, from_token_id)
+            .take(limit)
+            .collect();
+        let missing: BTreeSet<AccountId> = tokens
+            .iter()
+            .map(|(_, owner_id)| owner_id)
+            .filter(|owner_id| self.token_owners.get(owner_id).is_none())
+            .cloned()
+            .collect();
+
+        let owners: Vec<(AccountId, u64)> = iter_after(&self.token_owners, from_account_id)
+            .take(limit)
+            .collect();
+        let mut stale = Vec::new();
+        let mut miscounted = Vec::new();
+        for (owner_id, count) in &owners {
+            match tokens_per_owner.get(owner_id) {
+                None => stale.push(owner_id.clone()),
+                Some(tokens) if tokens.len() != *count => miscounted.push(owner_id.clone()),
+                _ => {}
+            }
+        }
+
+        OwnerIndexMismatches {
+            missing: missing.into_iter().collect(),
+            stale,
+            miscounted,
+            last_token_id: (tokens.len() == limit)
+                .then(|| tokens.last().map(|(token_id, _)| token_id.clone()))
+                .flatten(),
+            last_account_id: (owners.len() == limit)
+                .then(|| owners.last().map(|(owner_id, _)| owner_id.clone()))
+                .flatten(),
+        }
+    }
+
+    /// Fix mismatches found by `owner_index_mismatches` and return them.
+    /// Owners added to the index are considered holders since this call.
+    pub fn rebuild_owner_index(
+        &mut self,
+        from_token_id: Option<TokenId>,
+        from_account_id: Option<AccountId>,
+        limit: Option<U64>,
+    ) -> OwnerIndexMismatches {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.tokens.owner_id,
+            "Unauthorized"
+        );
+        self.assert_tokens_not_frozen();
+        let mismatches = self.owner_index_mismatches(from_token_id, from_account_id, limit);
+        let mismatched = mismatches
+            .missing
+            .iter()
+            .chain(&mismatches.stale)
+            .chain(&mismatches.miscounted);
+        for owner_id in mismatched {
+            self.update_owner_count(owner_id);
+        }
+        mismatches
+    }
+}
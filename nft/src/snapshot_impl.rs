@@ -0,0 +1,162 @@
+use near_contract_standards::non_fungible_token::refund_deposit;
+use near_sdk::collections::Vector;
+use near_sdk::require;
+
+use crate::*;
+
+/// Holders and their token counts at a block height. Built in chunks of holders while tokens are
+/// frozen, and never changes once complete.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct Snapshot {
+    pub block_height: u64,
+    pub timestamp: u64,
+    pub holders: Vector<(AccountId, u64)>,
+    /// Last recorded holder, the next chunk starts after it.
+    pub last_account_id: Option<AccountId>,
+    pub complete: bool,
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct SnapshotInfo {
+    pub block_height: U64,
+    pub timestamp: U64,
+    pub holders_count: U64,
+    pub complete: bool,
+}
+
+#[near_bindgen]
+impl Contract {
+    /// Start recording current holders and their token counts under `name`, with the first `limit`
+    /// holders. The snapshot is complete once a chunk has fewer than `limit` holders, otherwise
+    /// the rest is recorded by `snapshot_continue`. Tokens can't be minted, transferred or burned
+    /// until then.
+    /// Requires attached deposit to cover storage of the snapshot, the rest is refunded.
+    #[payable]
+    pub fn snapshot_create(&mut self, name: String, limit: Option<U64>) -> SnapshotInfo {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.tokens.owner_id,
+            "Unauthorized"
+        );
+        require!(
+            self.snapshots.get(&name).is_none(),
+            "Snapshot already exists"
+        );
+        require!(
+            self.recording_snapshot.is_none(),
+            "Another snapshot is being recorded"
+        );
+        require!(self.tokens_migration.is_none(), "Tokens are being migrated");
+        require!(
+            self.transfer_calls_in_flight == 0,
+            "Transfers with a call are being resolved"
+        );
+        let initial_storage_usage = env::storage_usage();
+
+        let mut snapshot = Snapshot {
+            block_height: env::block_height(),
+            timestamp: env::block_timestamp(),
+            holders: Vector::new(StorageKey::SnapshotHolders {
+                name_hash: env::sha256(name.as_bytes()),
+            }),
+            last_account_id: None,
+            complete: false,
+        };
+        self.record_holders(&name, &mut snapshot, limit);
+        self.snapshots.insert(&name, &snapshot);
+
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+        snapshot.into()
+    }
+
+    /// Record the next `limit` holders of snapshot `name`.
+    /// Requires attached deposit to cover storage of the chunk, the rest is refunded.
+    #[payable]
+    pub fn snapshot_continue(&mut self, name: String, limit: Option<U64>) -> SnapshotInfo {
+        assert_eq!(
+            env::predecessor_account_id(),
+            self.tokens.owner_id,
+            "Unauthorized"
+        );
+        let mut snapshot = self
+            .snapshots
+            .get(&name)
+            .unwrap_or_else(|| env::panic_str("Snapshot not found"));
+        require!(!snapshot.complete, "Snapshot is already complete");
+        let initial_storage_usage = env::storage_usage();
+
+        self.record_holders(&name, &mut snapshot, limit);
+        self.snapshots.insert(&name, &snapshot);
+
+        refund_deposit(env::storage_usage() - initial_storage_usage);
+        snapshot.into()
+    }
+
+    pub fn snapshot_info(&self, name: String) -> Option<SnapshotInfo> {
+        self.snapshots.get(&name).map(Into::into)
+    }
+
+    /// Holders recorded in snapshot `name` with the number of tokens they held.
+    pub fn snapshot_holders(
+        &self,
+        name: String,
+        from_index: Option<U64>,
+        limit: Option<U64>,
+    ) -> Vec<(AccountId, U128)> {
+        let snapshot = self
+            .snapshots
+            .get(&name)
+            .unwrap_or_else(|| env::panic_str("Snapshot not found"));
+        require!(snapshot.complete, "Snapshot is not complete");
+        let from_index = from_index.map(|index| index.0).unwrap_or(0);
+        let limit = limit.map(|limit| limit.0).unwrap_or(u64::MAX);
+
+        snapshot
+            .holders
+            .iter()
+            .skip(from_index as usize)
+            .take(limit as usize)
+            .map(|(owner_id, tokens)| (owner_id, U128(tokens as u128)))
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Append up to `limit` holders following the last recorded one to snapshot `name`.
+    /// Tokens stay frozen until the snapshot is complete.
+    fn record_holders(&mut self, name: &str, snapshot: &mut Snapshot, limit: Option<U64>) {
+        let limit = limit.map(|limit| limit.0).unwrap_or(u64::MAX) as usize;
+        let mut recorded = 0;
+        for holder in iter_after(&self.token_owners, snapshot.last_account_id.clone()).take(limit) {
+            snapshot.holders.push(&holder);
+            snapshot.last_account_id = Some(holder.0);
+            recorded += 1;
+        }
+        snapshot.complete = recorded < limit;
+        self.recording_snapshot = if snapshot.complete {
+            None
+        } else {
+            Some(name.to_string())
+        };
+    }
+
+    /// Panics while a snapshot is being recorded.
+    pub(crate) fn assert_tokens_not_frozen(&self) {
+        require!(
+            self.recording_snapshot.is_none(),
+            "Tokens are frozen while a snapshot is being recorded"
+        );
+    }
+}
+
+impl From<Snapshot> for SnapshotInfo {
+    fn from(snapshot: Snapshot) -> Self {
+        Self {
+            block_height: U64(snapshot.block_height),
+            timestamp: U64(snapshot.timestamp),
+            holders_count: U64(snapshot.holders.len()),
+            complete: snapshot.complete,
+        }
+    }
+}
@@ -0,0 +1,281 @@
+use near_contract_standards::non_fungible_token::refund_deposit;
+use near_sdk::collections::{LookupMap, Vector};
+use near_sdk::require;
+
+use crate::*;
+
+/// Voting power of an account or total supply of tokens recorded at a block height.
+#[derive(BorshDeserialize, BorshSerialize, Clone, Copy)]
+pub struct Checkpoint {
+    pub block_height: u64,
+    pub value: u64,
+}
+
+/// Number of blocks an account's checkpoints are kept for, about 30 days: longer than the proposal
+/// period of a DAO, so votes can be counted as of the creation of any open proposal.
+pub const CHECKPOINT_RETENTION_BLOCKS: u64 = 30 * 24 * 60 * 60;
+/// Number of expired checkpoints removed by each new checkpoint of an account.
+const MAX_PRUNED_CHECKPOINTS: u64 = 2;
+
+/// Checkpoints of an account's voting power from `start` to `end`. Checkpoints older than
+/// `CHECKPOINT_RETENTION_BLOCKS` are removed from the front as new ones are written, so the history
+/// of an account doesn't grow with every transfer while recent changes can't push it out.
+#[derive(BorshDeserialize, BorshSerialize)]
+pub struct AccountCheckpoints {
+    checkpoints: LookupMap<u64, Checkpoint>,
+    start: u64,
+    end: u64,
+}
+
+impl AccountCheckpoints {
+    fn new(account_id: &AccountId) -> Self {
+        Self {
+            checkpoints: LookupMap::new(StorageKey::AccountCheckpoints {
+                account_hash: env::sha256(account_id.as_bytes()),
+            }),
+            start: 0,
+            end: 0,
+        }
+    }
+
+    fn len(&self) -> u64 {
+        self.end - self.start
+    }
+
+    /// Whether checkpoints older than the kept ones were removed.
+    pub(crate) fn is_pruned(&self) -> bool {
+        self.start > 0
+    }
+
+    /// Checkpoint `index`, counting from the oldest one kept.
+    fn get(&self, index: u64) -> Checkpoint {
+        self.checkpoints.get(&(self.start + index)).unwrap()
+    }
+
+    fn write(&mut self, value: u64) {
+        let checkpoint = Checkpoint {
+            block_height: env::block_height(),
+            value,
+        };
+        let len = self.len();
+        if len > 0 && self.get(len - 1).block_height == checkpoint.block_height {
+            self.checkpoints.insert(&(self.end - 1), &checkpoint);
+        } else {
+            self.checkpoints.insert(&self.end, &checkpoint);
+            self.end += 1;
+        }
+        self.prune(
+            checkpoint
+                .block_height
+                .saturating_sub(CHECKPOINT_RETENTION_BLOCKS),
+        );
+    }
+
+    /// Remove checkpoints that are no longer needed to answer queries at or after `block_height`:
+    /// all but the last one recorded at or before it.
+    fn prune(&mut self, block_height: u64) {
+        for _ in 0..MAX_PRUNED_CHECKPOINTS {
+            if self.len() < 2 || self.get(1).block_height > block_height {
+                return;
+            }
+            self.checkpoints.remove(&self.start);
+            self.start += 1;
+        }
+    }
+}
+
+/// Voting power of token owners, one vote per token.
+///
+/// Owners vote for themselves until they delegate to another account with `delegate`.
+/// Votes follow tokens on every mint and transfer: they move from the delegate of the previous
+/// owner to the delegate of the new one. Every change is checkpointed by block height, so
+/// proposals can count votes as of the block they were created in.
+#[near_bindgen]
+impl Contract {
+    /// Delegate voting power of all caller's tokens, current and future, to `delegatee`.
+    /// Delegating to the caller itself removes the delegation.
+    /// Requires attached deposit to cover storage of a new delegation, the rest is refunded.
+    #[payable]
+    pub fn delegate(&mut self, delegatee: AccountId) {
+        require!(self.tokens_migration.is_none(), "Tokens are being migrated");
+        let account_id = env::predecessor_account_id();
+        let initial_storage_usage = env::storage_usage();
+        let tokens = self.tokens_count(&account_id);
+
+        self.move_votes(Some(&account_id), None, tokens);
+        if delegatee == account_id {
+            self.delegates.remove(&account_id);
+        } else {
+            self.delegates.insert(&account_id, &delegatee);
+        }
+        self.move_votes(None, Some(&account_id), tokens);
+
+        refund_deposit(env::storage_usage().saturating_sub(initial_storage_usage));
+    }
+
+    /// Account voting with tokens of `account_id`.
+    pub fn delegates(&self, account_id: AccountId) -> AccountId {
+        self.delegate_of(&account_id)
+    }
+
+    /// Voting power of `account_id`: tokens it owns and hasn't delegated plus tokens delegated to it.
+    pub fn get_votes(&self, account_id: AccountId) -> U128 {
+        U128(self.votes.get(&account_id).unwrap_or(0) as u128)
+    }
+
+    /// Voting power of `account_id` at the end of `block_height`, which must be in the past.
+    /// Checkpoints are kept for `CHECKPOINT_RETENTION_BLOCKS`, older blocks may not be queryable.
+    pub fn get_past_votes(&self, account_id: AccountId, block_height: U64) -> U128 {
+        assert_past_block(block_height.0);
+        let checkpoints = match self.checkpoints.get(&account_id) {
+            Some(checkpoints) => checkpoints,
+            None => return U128(0),
+        };
+        let index = checkpoint_index(checkpoints.len(), block_height.0, |index| {
+            checkpoints.get(index)
+        });
+        let value = match index {
+            Some(index) => checkpoints.get(index).value,
+            None => {
+                require!(
+                    !checkpoints.is_pruned(),
+                    "Block height is older than the kept checkpoints"
+                );
+                0
+            }
+        };
+        U128(value as u128)
+    }
+
+    /// Number of tokens at the end of `block_height`, which must be in the past.
+    pub fn get_past_total_supply(&self, block_height: U64) -> U128 {
+        assert_past_block(block_height.0);
+        let checkpoints = &self.total_supply_checkpoints;
+        let value = checkpoint_index(checkpoints.len(), block_height.0, |index| {
+            checkpoints.get(index).unwrap()
+        })
+        .map(|index| checkpoints.get(index).unwrap().value)
+        .unwrap_or(0);
+        U128(value as u128)
+    }
+
+    /// Voting power of every account that has votes.
+    pub fn nft_votes(&self) -> HashMap<AccountId, U128> {
+        self.votes
+            .iter()
+            .map(|(account_id, votes)| (account_id, U128(votes as u128)))
+            .collect()
+    }
+}
+
+impl Contract {
+    /// Move voting power of `amount` tokens from the delegate of `from_owner` to the delegate of
+    /// `to_owner`. `None` stands for tokens being minted or leaving an owner.
+    pub(crate) fn move_votes(
+        &mut self,
+        from_owner: Option<&AccountId>,
+        to_owner: Option<&AccountId>,
+        amount: u64,
+    ) {
+        let from = from_owner.map(|owner_id| self.delegate_of(owner_id));
+        let to = to_owner.map(|owner_id| self.delegate_of(owner_id));
+        if amount == 0 || from == to {
+            return;
+        }
+
+        if let Some(from) = from {
+            let votes = self.votes.get(&from).unwrap_or(0) - amount;
+            if votes == 0 {
+                self.votes.remove(&from);
+            } else {
+                self.votes.insert(&from, &votes);
+            }
+            self.write_votes_checkpoint(&from, votes);
+        }
+        if let Some(to) = to {
+            let votes = self.votes.get(&to).unwrap_or(0) + amount;
+            self.votes.insert(&to, &votes);
+            self.write_votes_checkpoint(&to, votes);
+        }
+    }
+
+    /// Move the vote of `token_id`, unless the token is yet to be counted by `migrate_tokens`,
+    /// which gives its vote to whoever owns it then.
+    pub(crate) fn move_token_votes(
+        &mut self,
+        token_id: &TokenId,
+        from_owner: Option<&AccountId>,
+        to_owner: Option<&AccountId>,
+    ) {
+        if self.is_token_counted(token_id) {
+            self.move_votes(from_owner, to_owner, 1);
+        }
+    }
+
+    /// Record the current number of tokens. Called whenever tokens are minted or burned.
+    pub(crate) fn write_total_supply_checkpoint(&mut self) {
+        let total_supply = self.tokens.owner_by_id.len();
+        write_checkpoint(&mut self.total_supply_checkpoints, total_supply);
+    }
+
+    fn write_votes_checkpoint(&mut self, account_id: &AccountId, votes: u64) {
+        let mut checkpoints = self
+            .checkpoints
+            .get(account_id)
+            .unwrap_or_else(|| AccountCheckpoints::new(account_id));
+        checkpoints.write(votes);
+        self.checkpoints.insert(account_id, &checkpoints);
+    }
+
+    fn delegate_of(&self, account_id: &AccountId) -> AccountId {
+        self.delegates
+            .get(account_id)
+            .unwrap_or_else(|| account_id.clone())
+    }
+
+    pub(crate) fn tokens_count(&self, account_id: &AccountId) -> u64 {
+        self.tokens
+            .tokens_per_owner
+            .as_ref()
+            .unwrap()
+            .get(account_id)
+            .map(|tokens| tokens.len())
+            .unwrap_or(0)
+    }
+}
+
+fn assert_past_block(block_height: u64) {
+    require!(
+        block_height < env::block_height(),
+        "Block height must be in the past"
+    );
+}
+
+/// Push a checkpoint for the current block, or update it if the block already has one.
+fn write_checkpoint(checkpoints: &mut Vector<Checkpoint>, value: u64) {
+    let checkpoint = Checkpoint {
+        block_height: env::block_height(),
+        value,
+    };
+    match checkpoints.len().checked_sub(1) {
+        Some(last) if checkpoints.get(last).unwrap().block_height == checkpoint.block_height => {
+            checkpoints.replace(last, &checkpoint);
+        }
+        _ => checkpoints.push(&checkpoint),
+    }
+}
+
+/// Index of the last of `len` checkpoints recorded at or before `block_height`.
+fn checkpoint_index(len: u64, block_height: u64, get: impl Fn(u64) -> Checkpoint) -> Option<u64> {
+    // Number of checkpoints recorded at or before `block_height`
+    let (mut low, mut high) = (0, len);
+    while low < high {
+        let mid = low + (high - low) / 2;
+        if get(mid).block_height <= block_height {
+            low = mid + 1;
+        } else {
+            high = mid;
+        }
+    }
+    low.checked_sub(1)
+}
@@ -0,0 +1,446 @@
+/*!
+Staking contract for AstroDAO token-weighted voting backed by NFT holdings.
+NOTES:
+  - The contract implements the interface of the Sputnik staking contract: the DAO is the `owner_id`,
+    stakers register with `storage_deposit`, which registers their delegation in the DAO, and
+    `delegate` / `undelegate` forward voting power to the DAO.
+  - Tokens are staked by transferring them with `nft_transfer_call` to this contract. Every staked
+    token is worth one vote unless the owner (DAO) sets a different weight for it with `set_token_weights`.
+    The weight is fixed when a token is staked, so unstaking always releases the same amount.
+  - A token can only be unstaked once its weight is not delegated anymore and `unstake_period`
+    has passed since the last `undelegate`, so the same token can't be used to vote twice.
+*/
+
+use near_contract_standards::non_fungible_token::core::NonFungibleTokenReceiver;
+use near_contract_standards::non_fungible_token::TokenId;
+use near_sdk::borsh::{self, BorshDeserialize, BorshSerialize};
+use near_sdk::collections::LookupMap;
+use near_sdk::json_types::{U128, U64};
+use near_sdk::serde::{Deserialize, Serialize};
+use near_sdk::{
+    env, ext_contract, near_bindgen, require, AccountId, Balance, BorshStorageKey, Gas,
+    PanicOnDefault, Promise, PromiseOrValue, PromiseResult, StorageUsage,
+};
+
+/// Vote weight of a token without a weight set by the owner.
+const DEFAULT_TOKEN_WEIGHT: Balance = 1;
+/// Storage of a user record without staked tokens and delegations.
+const USER_STORAGE: StorageUsage = 300;
+/// Storage of a staked token or a delegation in a user record.
+const ITEM_STORAGE: StorageUsage = 100;
+/// Storage taken by the DAO to register a delegation.
+const DAO_DELEGATION_STORAGE: StorageUsage = 16;
+
+const TGAS_DAO_CALL: u64 = 10;
+const TGAS_NFT_TRANSFER: u64 = 20;
+const TGAS_ON_UNSTAKE: u64 = 10;
+
+#[ext_contract(ext_dao)]
+pub trait Dao {
+    fn register_delegation(&mut self, account_id: AccountId);
+    fn delegate(&mut self, account_id: AccountId, amount: U128) -> (U128, U128, U128);
+    fn undelegate(&mut self, account_id: AccountId, amount: U128) -> (U128, U128, U128);
+}
+
+#[ext_contract(ext_nft)]
+pub trait NonFungibleToken {
+    fn nft_transfer(
+        &mut self,
+        receiver_id: AccountId,
+        token_id: TokenId,
+        approval_id: Option<u64>,
+        memo: Option<String>,
+    );
+}
+
+#[derive(BorshDeserialize, BorshSerialize, Default)]
+pub struct User {
+    /// NEAR deposited to cover storage of the user record.
+    storage_balance: Balance,
+    /// Staked tokens with the weight they had when staked.
+    tokens: Vec<(TokenId, Balance)>,
+    /// Voting power delegated to accounts in the DAO.
+    delegated_amounts: Vec<(AccountId, Balance)>,
+    /// Tokens can't be unstaked before this block timestamp.
+    next_action_timestamp: u64,
+}
+
+impl User {
+    fn vote_amount(&self) -> Balance {
+        self.tokens.iter().map(|(_, weight)| weight).sum()
+    }
+
+    fn delegated_amount(&self) -> Balance {
+        self.delegated_amounts
+            .iter()
+            .map(|(_, amount)| amount)
+            .sum()
+    }
+
+    fn storage_required(&self, extra_items: usize) -> Balance {
+        let items = self.tokens.len() + self.delegated_amounts.len() + extra_items;
+        (USER_STORAGE + ITEM_STORAGE * items as StorageUsage) as Balance * env::storage_byte_cost()
+    }
+}
+
+#[derive(Deserialize, Serialize)]
+#[serde(crate = "near_sdk::serde")]
+pub struct UserView {
+    pub storage_balance: U128,
+    pub vote_amount: U128,
+    pub tokens: Vec<(TokenId, U128)>,
+    pub delegated_amounts: Vec<(AccountId, U128)>,
+    pub next_action_timestamp: U64,
+}
+
+impl From<User> for UserView {
+    fn from(user: User) -> Self {
+        Self {
+            storage_balance: U128(user.storage_balance),
+            vote_amount: U128(user.vote_amount()),
+            tokens: user
+                .tokens
+                .iter()
+                .map(|(token_id, weight)| (token_id.clone(), U128(*weight)))
+                .collect(),
+            delegated_amounts: user
+                .delegated_amounts
+                .iter()
+                .map(|(account_id, amount)| (account_id.clone(), U128(*amount)))
+                .collect(),
+            next_action_timestamp: U64(user.next_action_timestamp),
+        }
+    }
+}
+
+#[near_bindgen]
+#[derive(BorshDeserialize, BorshSerialize, PanicOnDefault)]
+pub struct Contract {
+    /// DAO contract that reads voting power from this contract.
+    owner_id: AccountId,
+    nft_contract_id: AccountId,
+    /// Time in nanoseconds after `undelegate` before tokens can be unstaked.
+    unstake_period: u64,
+    users: LookupMap<AccountId, User>,
+    token_weights: LookupMap<TokenId, Balance>,
+    /// Total weight of staked tokens.
+    total_amount: Balance,
+}
+
+#[derive(BorshSerialize, BorshStorageKey)]
+enum StorageKey {
+    Users,
+    TokenWeights,
+}
+
+#[near_bindgen]
+impl Contract {
+    #[init]
+    pub fn new(owner_id: AccountId, nft_contract_id: AccountId, unstake_period: U64) -> Self {
+        assert!(!env::state_exists(), "Already initialized");
+        Self {
+            owner_id,
+            nft_contract_id,
+            unstake_period: unstake_period.0,
+            users: LookupMap::new(StorageKey::Users),
+            token_weights: LookupMap::new(StorageKey::TokenWeights),
+            total_amount: 0,
+        }
+    }
+
+    /// Register the user and its delegation in the DAO, or top up its storage balance.
+    #[payable]
+    pub fn storage_deposit(&mut self, account_id: Option<AccountId>) -> UserView {
+        let account_id = account_id.unwrap_or_else(env::predecessor_account_id);
+        let mut deposit = env::attached_deposit();
+        let mut user = match self.users.get(&account_id) {
+            Some(user) => user,
+            None => {
+                let registration = DAO_DELEGATION_STORAGE as Balance * env::storage_byte_cost();
+                let user = User::default();
+                require!(
+                    deposit >= user.storage_required(0) + registration,
+                    format!(
+                        "Requires attached deposit of at least {} yoctoNEAR",
+                        user.storage_required(0) + registration
+                    )
+                );
+                deposit -= registration;
+                ext_dao::ext(self.owner_id.clone())
+                    .with_attached_deposit(registration)
+                    .with_static_gas(Gas::ONE_TERA * TGAS_DAO_CALL)
+                    .register_delegation(account_id.clone());
+                user
+            }
+        };
+        user.storage_balance += deposit;
+        self.users.insert(&account_id, &user);
+        user.into()
+    }
+
+    /// Unstake `token_id` and transfer it back to the caller.
+    pub fn unstake(&mut self, token_id: TokenId) -> Promise {
+        let account_id = env::predecessor_account_id();
+        let mut user = self.expect_user(&account_id);
+        require!(
+            env::block_timestamp() >= user.next_action_timestamp,
+            "Unstake period has not passed yet"
+        );
+        let index = user
+            .tokens
+            .iter()
+            .position(|(staked_id, _)| *staked_id == token_id)
+            .unwrap_or_else(|| env::panic_str("Token is not staked"));
+        let (_, weight) = user.tokens[index];
+        require!(
+            user.vote_amount() - user.delegated_amount() >= weight,
+            "Token weight is delegated"
+        );
+        user.tokens.swap_remove(index);
+        self.users.insert(&account_id, &user);
+        self.total_amount -= weight;
+
+        ext_nft::ext(self.nft_contract_id.clone())
+            .with_attached_deposit(1)
+            .with_static_gas(Gas::ONE_TERA * TGAS_NFT_TRANSFER)
+            .nft_transfer(account_id.clone(), token_id.clone(), None, None)
+            .then(
+                Self::ext(env::current_account_id())
+                    .with_static_gas(Gas::ONE_TERA * TGAS_ON_UNSTAKE)
+                    .on_unstake(account_id, token_id, U128(weight)),
+            )
+    }
+
+    /// Restores the staked token if the transfer back to the user failed.
+    #[private]
+    pub fn on_unstake(&mut self, account_id: AccountId, token_id: TokenId, weight: U128) -> bool {
+        if let PromiseResult::Successful(_) = env::promise_result(0) {
+            return true;
+        }
+        let mut user = self.expect_user(&account_id);
+        user.tokens.push((token_id, weight.0));
+        self.users.insert(&account_id, &user);
+        self.total_amount += weight.0;
+        false
+    }
+
+    /// Delegate `amount` of the caller's voting power to `account_id` in the DAO.
+    pub fn delegate(&mut self, account_id: AccountId, amount: U128) -> Promise {
+        let sender_id = env::predecessor_account_id();
+        require!(
+            self.users.contains_key(&account_id),
+            "Delegate is not registered"
+        );
+        let mut user = self.expect_user(&sender_id);
+        require!(
+            amount.0 > 0 && user.vote_amount() - user.delegated_amount() >= amount.0,
+            "Not enough voting power to delegate"
+        );
+        match user
+            .delegated_amounts
+            .iter_mut()
+            .find(|(delegate_id, _)| *delegate_id == account_id)
+        {
+            Some((_, delegated)) => *delegated += amount.0,
+            None => {
+                require!(
+                    user.storage_balance >= user.storage_required(1),
+                    "Not enough storage balance"
+                );
+                user.delegated_amounts.push((account_id.clone(), amount.0));
+            }
+        }
+        self.users.insert(&sender_id, &user);
+
+        ext_dao::ext(self.owner_id.clone())
+            .with_static_gas(Gas::ONE_TERA * TGAS_DAO_CALL)
+            .delegate(account_id, amount)
+    }
+
+    /// Take back `amount` of the caller's voting power delegated to `account_id` in the DAO.
+    /// Tokens can't be unstaked until `unstake_period` has passed.
+    pub fn undelegate(&mut self, account_id: AccountId, amount: U128) -> Promise {
+        let sender_id = env::predecessor_account_id();
+        let mut user = self.expect_user(&sender_id);
+        let index = user
+            .delegated_amounts
+            .iter()
+            .position(|(delegate_id, _)| *delegate_id == account_id)
+            .unwrap_or_else(|| env::panic_str("No delegation to the account"));
+        let delegated = &mut user.delegated_amounts[index].1;
+        require!(*delegated >= amount.0, "Not enough delegated voting power");
+        *delegated -= amount.0;
+        if *delegated == 0 {
+            user.delegated_amounts.swap_remove(index);
+        }
+        user.next_action_timestamp = env::block_timestamp() + self.unstake_period;
+        self.users.insert(&sender_id, &user);
+
+        ext_dao::ext(self.owner_id.clone())
+            .with_static_gas(Gas::ONE_TERA * TGAS_DAO_CALL)
+            .undelegate(account_id, amount)
+    }
+
+    /// Set vote weights of tokens staked from now on.
+    pub fn set_token_weights(&mut self, weights: Vec<(TokenId, U128)>) {
+        self.assert_owner();
+        for (token_id, weight) in weights {
+            self.token_weights.insert(&token_id, &weight.0);
+        }
+    }
+
+    pub fn get_token_weight(&self, token_id: TokenId) -> U128 {
+        U128(self.token_weight(&token_id))
+    }
+
+    /// Total weight of staked tokens.
+    pub fn ft_total_supply(&self) -> U128 {
+        U128(self.total_amount)
+    }
+
+    /// Weight of tokens staked by `account_id`.
+    pub fn ft_balance_of(&self, account_id: AccountId) -> U128 {
+        U128(
+            self.users
+                .get(&account_id)
+                .map(|user| user.vote_amount())
+                .unwrap_or(0),
+        )
+    }
+
+    pub fn get_user(&self, account_id: AccountId) -> UserView {
+        self.expect_user(&account_id).into()
+    }
+
+    pub fn get_nft_contract_id(&self) -> AccountId {
+        self.nft_contract_id.clone()
+    }
+
+    fn assert_owner(&self) {
+        assert_eq!(self.owner_id, env::predecessor_account_id());
+    }
+}
+
+impl Contract {
+    fn expect_user(&self, account_id: &AccountId) -> User {
+        self.users
+            .get(account_id)
+            .unwrap_or_else(|| env::panic_str("User is not registered"))
+    }
+
+    fn token_weight(&self, token_id: &TokenId) -> Balance {
+        self.token_weights
+            .get(token_id)
+            .unwrap_or(DEFAULT_TOKEN_WEIGHT)
+    }
+}
+
+#[near_bindgen]
+impl NonFungibleTokenReceiver for Contract {
+    /// Stake the token for `previous_owner_id`. The token is returned if the owner is not
+    /// registered or its storage balance doesn't cover one more token.
+    #[allow(unused_variables)]
+    fn nft_on_transfer(
+        &mut self,
+        sender_id: AccountId,
+        previous_owner_id: AccountId,
+        token_id: TokenId,
+        msg: String,
+    ) -> PromiseOrValue<bool> {
+        require!(
+            env::predecessor_account_id() == self.nft_contract_id,
+            "Only tokens of the NFT contract can be staked"
+        );
+        let mut user = match self.users.get(&previous_owner_id) {
+            Some(user) if user.storage_balance >= user.storage_required(1) => user,
+            _ => return PromiseOrValue::Value(true),
+        };
+        let weight = self.token_weight(&token_id);
+        user.tokens.push((token_id, weight));
+        self.users.insert(&previous_owner_id, &user);
+        self.total_amount += weight;
+        PromiseOrValue::Value(false)
+    }
+}
+
+#[cfg(all(test, not(target_arch = "wasm32")))]
+mod tests {
+    use near_sdk::test_utils::{accounts, VMContextBuilder};
+    use near_sdk::{testing_env, ONE_NEAR};
+
+    use super::*;
+
+    const UNSTAKE_PERIOD: u64 = 1_000;
+
+    fn get_context(predecessor_account_id: AccountId) -> VMContextBuilder {
+        let mut builder = VMContextBuilder::new();
+        builder
+            .current_account_id(accounts(0))
+            .signer_account_id(predecessor_account_id.clone())
+            .predecessor_account_id(predecessor_account_id);
+        builder
+    }
+
+    /// DAO is `accounts(1)`, NFT contract is `accounts(2)`.
+    fn setup_contract() -> Contract {
+        testing_env!(get_context(accounts(1)).build());
+        Contract::new(accounts(1), accounts(2), U64(UNSTAKE_PERIOD))
+    }
+
+    fn register(contract: &mut Contract, account_id: AccountId) {
+        testing_env!(get_context(account_id).attached_deposit(ONE_NEAR).build());
+        contract.storage_deposit(None);
+    }
+
+    fn stake(contract: &mut Contract, owner_id: AccountId, token_id: &str) -> bool {
+        testing_env!(get_context(accounts(2)).build());
+        match contract.nft_on_transfer(owner_id.clone(), owner_id, token_id.into(), String::new()) {
+            PromiseOrValue::Value(return_token) => return_token,
+            PromiseOrValue::Promise(_) => panic!("Expected a value"),
+        }
+    }
+
+    #[test]
+    fn test_stake_counts_token_weights() {
+        let mut contract = setup_contract();
+        contract.set_token_weights(vec![("legendary".into(), U128(5))]);
+        register(&mut contract, accounts(3));
+
+        assert!(!stake(&mut contract, accounts(3), "common"));
+        assert!(!stake(&mut contract, accounts(3), "legendary"));
+        assert_eq!(contract.ft_balance_of(accounts(3)), U128(6));
+        assert_eq!(contract.ft_total_supply(), U128(6));
+        assert_eq!(contract.ft_balance_of(accounts(4)), U128(0));
+    }
+
+    #[test]
+    fn test_unregistered_owner_gets_token_back() {
+        let mut contract = setup_contract();
+        assert!(stake(&mut contract, accounts(3), "0"));
+        assert_eq!(contract.ft_total_supply(), U128(0));
+    }
+
+    #[test]
+    fn test_unstake_after_undelegate() {
+        let mut contract = setup_contract();
+        register(&mut contract, accounts(3));
+        register(&mut contract, accounts(4));
+        stake(&mut contract, accounts(3), "0");
+
+        testing_env!(get_context(accounts(3)).build());
+        contract.delegate(accounts(4), U128(1));
+        assert_eq!(
+            contract.get_user(accounts(3)).delegated_amounts,
+            vec![(accounts(4), U128(1))]
+        );
+        contract.undelegate(accounts(4), U128(1));
+        assert!(contract.get_user(accounts(3)).delegated_amounts.is_empty());
+
+        testing_env!(get_context(accounts(3))
+            .block_timestamp(UNSTAKE_PERIOD)
+            .build());
+        contract.unstake("0".into());
+        assert_eq!(contract.ft_balance_of(accounts(3)), U128(0));
+        assert_eq!(contract.ft_total_supply(), U128(0));
+    }
+}